@@ -0,0 +1,80 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::time::{Duration, Instant};
+
+use crate::evaluation::ClassicalEvaluator;
+use crate::parameters::EvalParameters;
+use crate::search::{SearchConfig, SearchLimits, Searcher};
+use crate::ui::from_fen;
+
+// Openings, middlegames and endgames, each searched with empty tables so that the node counts
+// only depend on the engine
+pub const BENCH_FENS: [&str; 12] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 2",
+    "r3k2r/1p3ppp/1qnbpn2/pP1p4/3P1P2/2PB1Q2/P2N2PP/R1B2RK1 b kq - 0 12",
+    "r1bq1rk1/1p3pp1/p2p1n1p/2b1p3/2PnP3/P1NB4/1P1QNPPP/R1B2RK1 b - - 0 12",
+    "r2q1rk1/1b2b1pp/p1p1p3/2npPp2/3N1P2/2N1B3/PPP3PP/2RQ1RK1 w - - 0 1",
+    "r2qkb1r/5ppp/2np1n2/1N2p1B1/2b1P3/2N2P2/PPP3PP/R2QR1K1 b kq - 0 1",
+    "3r1r1k/1p2Nppp/p4n2/P1p1p3/4P3/6Pq/2P1NP2/R1B1QRK1 b - - 2 18",
+    "2kr3r/pp2nppp/4p3/2p1Nq2/P7/2P5/1PnB1PPP/R2QR1K1 w - - 1 19",
+    "8/6pk/3r1qpp/4N2P/3PQ3/8/5PP1/6K1 w - - 1 41",
+    "3N4/p6k/b3N1pp/3pp3/R4p2/7P/P1r3PB/6K1 b - - 1 34",
+    "8/8/1p6/p1p2k2/P1P5/1P3K2/8/8 w - - 0 1",
+    "4k3/8/8/3q4/4P3/8/8/R3K3 w - - 0 1",
+];
+
+pub const BENCH_DEPTH: i32 = 6;
+
+pub struct BenchResult {
+    pub nodes: u64,
+    pub duration: Duration,
+    // Changes with the node count or the move of any position, to spot functional changes
+    pub signature: u64,
+}
+
+impl BenchResult {
+    pub fn nodes_per_second(&self) -> u64 {
+        (self.nodes as f64 / self.duration.as_secs_f64().max(1e-9)) as u64
+    }
+}
+
+impl std::fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "nodes {} time {}ms nps {} signature {:016x}",
+            self.nodes,
+            self.duration.as_millis(),
+            self.nodes_per_second(),
+            self.signature
+        )
+    }
+}
+
+// Search all the BENCH_FENS to the given depth, only the duration depends on the machine
+pub fn bench(parameters: &EvalParameters, config: SearchConfig, depth: i32) -> BenchResult {
+    let mut nodes = 0;
+    let mut hasher = DefaultHasher::new();
+    let mut duration = Duration::default();
+    for fen in BENCH_FENS.iter() {
+        let mut searcher =
+            Searcher::with_evaluator(ClassicalEvaluator::with_parameters(parameters));
+        searcher.config = config;
+        let start = Instant::now();
+        let (top_move, _score, _depth, stats) =
+            searcher.search(from_fen(fen), SearchLimits::depth(depth));
+        duration += start.elapsed();
+        nodes += u64::from(stats.nodes);
+        hasher.write_u32(stats.nodes);
+        if let Some((start, end)) = top_move {
+            hasher.write_usize(start);
+            hasher.write_usize(end);
+        }
+    }
+    BenchResult {
+        nodes,
+        duration,
+        signature: hasher.finish(),
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use sunfish::epd::load_epd;
+use sunfish::search::{SearchLimits, Searcher};
+use sunfish::ui::render_san;
+
+const USAGE: &str = "Usage: epd FILE [--depth N] [--time MS] [--csv FILE]
+The depth or time is for the positions without their own acd, acn or acs";
+
+fn exit_with_error(error: &str) -> ! {
+    eprintln!("{}\n{}", error, USAGE);
+    std::process::exit(1);
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| {
+        arguments
+            .iter()
+            .position(|argument| argument == name)
+            .map(|index| match arguments.get(index + 1) {
+                Some(value) => value.clone(),
+                None => exit_with_error(&format!("Missing value after {}", name)),
+            })
+    };
+    let number = |name: &str| {
+        option(name).map(|value| {
+            value
+                .parse::<u32>()
+                .unwrap_or_else(|_| exit_with_error(&format!("Invalid {} {}", name, value)))
+        })
+    };
+
+    let path = match arguments.first() {
+        Some(path) if !path.starts_with("--") => path,
+        _ => exit_with_error("Missing EPD file"),
+    };
+    let mut limits = SearchLimits {
+        depth: number("--depth").map(|depth| depth as i32),
+        time: number("--time").map(|millis| Duration::from_millis(millis.into())),
+        ..SearchLimits::default()
+    };
+    if limits == SearchLimits::default() {
+        limits.time = Some(Duration::from_secs(1));
+    }
+    let positions = load_epd(path).unwrap_or_else(|error| exit_with_error(&error));
+
+    let mut csv = String::from("id,solved,move,expected,depth,score,nodes,milliseconds\n");
+    let mut solved_ids = Vec::new();
+    let mut failures = Vec::new();
+    let start_time = Instant::now();
+    for (index, position) in positions.iter().enumerate() {
+        let id = if position.id.is_empty() {
+            format!("#{}", index + 1)
+        } else {
+            position.id.clone()
+        };
+        let render = |moves: &[(usize, usize)]| {
+            moves
+                .iter()
+                .map(|move_| render_san(&position.board_state, position.am_black, move_))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let expected = if position.avoid_moves.is_empty() {
+            render(&position.best_moves)
+        } else {
+            format!(
+                "{} not {}",
+                render(&position.best_moves),
+                render(&position.avoid_moves)
+            )
+        };
+
+        let position_limits = if position.limits == SearchLimits::default() {
+            limits
+        } else {
+            position.limits
+        };
+        let mut searcher = Searcher::default();
+        let search_start_time = Instant::now();
+        let (top_move, score, depth, stats) =
+            searcher.search(position.board_state, position_limits);
+        let milliseconds = search_start_time.elapsed().as_millis();
+        let solved = top_move.is_some_and(|move_| position.is_solved_by(&move_));
+        let played = top_move.map_or("none".to_string(), |move_| render(&[move_]));
+        println!(
+            "{: <12} {} {} expected {} depth {} score {} nodes {} in {}ms",
+            id,
+            if solved { "solved" } else { "FAILED" },
+            played,
+            expected.trim(),
+            depth,
+            score,
+            stats.nodes,
+            milliseconds
+        );
+        if !solved && !position.comment.is_empty() {
+            println!("{: <12} {}", "", position.comment);
+        }
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{}",
+            id,
+            solved,
+            played,
+            expected.trim(),
+            depth,
+            score,
+            stats.nodes,
+            milliseconds
+        );
+        if solved {
+            solved_ids.push(id);
+        } else {
+            failures.push(id);
+        }
+    }
+
+    println!(
+        "Solved {}/{} in {:?}",
+        positions.len() - failures.len(),
+        positions.len(),
+        start_time.elapsed()
+    );
+    if !solved_ids.is_empty() {
+        println!("Solved: {}", solved_ids.join(" "));
+    }
+    if !failures.is_empty() {
+        println!("Failed: {}", failures.join(" "));
+    }
+    if let Some(csv_path) = option("--csv") {
+        std::fs::write(&csv_path, csv).unwrap_or_else(|error| {
+            exit_with_error(&format!("Can't write {}: {}", csv_path, error))
+        });
+    }
+}
@@ -0,0 +1,58 @@
+use sunfish::book::Book;
+use sunfish::pgn::load_pgn;
+
+const USAGE: &str = "Usage: make_book PGN... --output BOOK [--max-ply N] [--min-games N]
+Writes a Polyglot book with the moves of the first --max-ply half moves, 30 by default, played
+in at least --min-games games, 1 by default";
+
+fn exit_with_error(error: &str) -> ! {
+    eprintln!("{}\n{}", error, USAGE);
+    std::process::exit(1);
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| {
+        arguments
+            .iter()
+            .position(|argument| argument == name)
+            .map(|index| match arguments.get(index + 1) {
+                Some(value) => value.clone(),
+                None => exit_with_error(&format!("Missing value after {}", name)),
+            })
+    };
+    let number = |name: &str| {
+        option(name).map(|value| {
+            value
+                .parse::<u32>()
+                .unwrap_or_else(|_| exit_with_error(&format!("Invalid {} {}", name, value)))
+        })
+    };
+
+    let output = option("--output").unwrap_or_else(|| exit_with_error("Missing --output"));
+    let max_ply = number("--max-ply").unwrap_or(30) as usize;
+    let min_games = number("--min-games").unwrap_or(1);
+    // Arguments that aren't options or their values
+    let paths: Vec<&String> = arguments
+        .iter()
+        .enumerate()
+        .filter(|(index, argument)| {
+            !argument.starts_with("--") && (*index == 0 || !arguments[index - 1].starts_with("--"))
+        })
+        .map(|(_, argument)| argument)
+        .collect();
+    if paths.is_empty() {
+        exit_with_error("Missing PGN files");
+    }
+
+    let mut games = Vec::new();
+    for path in paths {
+        let loaded = load_pgn(path).unwrap_or_else(|error| exit_with_error(&error));
+        println!("{} games in {}", loaded.len(), path);
+        games.extend(loaded);
+    }
+    let book = Book::from_games(&games, max_ply, min_games);
+    book.save(&output)
+        .unwrap_or_else(|error| exit_with_error(&error));
+    println!("{} book entries written to {}", book.entries.len(), output);
+}
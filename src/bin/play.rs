@@ -0,0 +1,132 @@
+use std::io::{BufRead, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use sunfish::board::rotate;
+use sunfish::game::{Game, GameResult, INITIAL_FEN};
+use sunfish::parameters::DEFAULT_PARAMETERS;
+use sunfish::pgn::PgnGame;
+use sunfish::search::{SearchConfig, SearchLimits};
+use sunfish::selfplay::{Player, SearcherPlayer};
+use sunfish::ui::{parse_san, render_position, render_san};
+
+const USAGE: &str = "Usage: play [--black] [--time MS] [--fen FEN]";
+
+const HELP: &str = "Moves are in SAN like Nf3 or exd5, or UCI like g1f3
+undo      take back your last move
+hint      suggest a move
+time MS   think MS milliseconds per move
+pgn       show the game so far
+quit      leave the game";
+
+fn exit_with_error(error: &str) -> ! {
+    eprintln!("{}\n{}", error, USAGE);
+    std::process::exit(1);
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| {
+        arguments
+            .iter()
+            .position(|argument| argument == name)
+            .map(|index| match arguments.get(index + 1) {
+                Some(value) => value.clone(),
+                None => exit_with_error(&format!("Missing value after {}", name)),
+            })
+    };
+    let human_is_black = arguments.iter().any(|argument| argument == "--black");
+    let mut time = match option("--time") {
+        Some(millis) => millis
+            .parse()
+            .unwrap_or_else(|_| exit_with_error(&format!("Invalid --time {}", millis))),
+        None => 1000,
+    };
+    let mut game = Game::from_fen(&option("--fen").unwrap_or_else(|| INITIAL_FEN.to_string()));
+    let mut engine = SearcherPlayer {
+        name: "sunfish".to_string(),
+        parameters: Arc::new(DEFAULT_PARAMETERS.clone()),
+        config: SearchConfig::default(),
+    };
+
+    println!("Type help for the commands");
+    let mut lines = std::io::stdin().lock().lines();
+    let mut shown_moves = None;
+    loop {
+        // The board always shows the human's pieces at the bottom, and only when it changed
+        if shown_moves != Some(game.moves.clone()) {
+            let mut board_state = game.board_state();
+            if game.am_black() != human_is_black {
+                rotate(&mut board_state);
+            }
+            println!("\n{}", render_position(&board_state, human_is_black));
+            shown_moves = Some(game.moves.clone());
+        }
+        if let Some((result, reason)) = game.result() {
+            let winner = match (result, human_is_black) {
+                (GameResult::Draw, _) => "draw",
+                (GameResult::WhiteWins, false) | (GameResult::BlackWins, true) => "you win",
+                _ => "sunfish wins",
+            };
+            println!("{} by {}, {}", result, reason, winner);
+            break;
+        }
+        let limits = SearchLimits::time(Duration::from_millis(time));
+        if game.am_black() != human_is_black {
+            let move_ = engine.best_move(&game, limits).expect("searcher move");
+            println!(
+                "sunfish plays {}",
+                render_san(&game.board_state(), game.am_black(), &move_)
+            );
+            game.play(&move_).expect("legal searcher move");
+            continue;
+        }
+
+        print!("Your move: ");
+        std::io::stdout().flush().expect("can't write to stdout");
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => break,
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => {}
+            ["help"] => println!("{}", HELP),
+            ["quit"] | ["exit"] => break,
+            ["undo"] => {
+                // Back to the human's turn, taking back the engine's reply too
+                if game.undo().is_none() {
+                    println!("No move to take back");
+                }
+                if game.am_black() != human_is_black {
+                    game.undo();
+                }
+            }
+            ["hint"] => {
+                let move_ = engine.best_move(&game, limits).expect("searcher move");
+                println!(
+                    "Hint: {}",
+                    render_san(&game.board_state(), game.am_black(), &move_)
+                );
+            }
+            ["time", millis] => match millis.parse() {
+                Ok(millis) => time = millis,
+                Err(_) => println!("Invalid time {}", millis),
+            },
+            ["pgn"] => println!(
+                "{}",
+                PgnGame {
+                    game: game.clone(),
+                    ..PgnGame::default()
+                }
+            ),
+            _ => match parse_san(&game.board_state(), game.am_black(), &line) {
+                Some(move_) => game.play(&move_).expect("legal parsed move"),
+                None => println!(
+                    "Illegal or ambiguous move {}, type help for the commands",
+                    line.trim()
+                ),
+            },
+        }
+    }
+}
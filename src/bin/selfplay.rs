@@ -0,0 +1,165 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sunfish::parameters::{EvalParameters, DEFAULT_PARAMETERS};
+use sunfish::pgn::PgnGame;
+use sunfish::search::{SearchConfig, SearchLimits};
+use sunfish::selfplay::{
+    load_openings, parse_opening, play_game, MatchScore, Player, SearcherPlayer, SprtResult,
+    UciPlayer,
+};
+use sunfish::uci::set_search_option;
+
+const USAGE: &str = "Usage: selfplay [OPENINGS] [--first ENGINE] [--second ENGINE] \
+                     [--depth N] [--nodes N] [--time MS] [--games N] [--max-moves N] \
+                     [--elo0 ELO] [--elo1 ELO] [--alpha P] [--beta P] [--pgn FILE]
+ENGINE is sunfish, sunfish:Option=value,Option=value with the UCI options, or uci:COMMAND
+The games are appended to the PGN file";
+
+fn exit_with_error(error: &str) -> ! {
+    eprintln!("{}\n{}", error, USAGE);
+    std::process::exit(1);
+}
+
+fn engine(spec: &str) -> Box<dyn Player> {
+    if let Some(command) = spec.strip_prefix("uci:") {
+        return Box::new(UciPlayer::start(command).unwrap_or_else(|error| exit_with_error(&error)));
+    }
+    let mut parameters = Arc::new(DEFAULT_PARAMETERS.clone());
+    let mut config = SearchConfig::default();
+    let options = match spec.split_once(':') {
+        Some(("sunfish", options)) => options,
+        None if spec == "sunfish" => "",
+        _ => exit_with_error(&format!("Unknown engine {}", spec)),
+    };
+    for option in options.split(',').filter(|option| !option.is_empty()) {
+        match option.split_once('=') {
+            Some(("EvalFile", path)) => {
+                parameters = Arc::new(
+                    EvalParameters::load(path).unwrap_or_else(|error| exit_with_error(&error)),
+                )
+            }
+            Some((name, value)) if set_search_option(&mut config, name, value) => {}
+            _ => exit_with_error(&format!("Unknown option {}", option)),
+        }
+    }
+    Box::new(SearcherPlayer {
+        name: spec.to_string(),
+        parameters,
+        config,
+    })
+}
+
+fn main() {
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| {
+        arguments
+            .iter()
+            .position(|argument| argument == name)
+            .map(|index| match arguments.get(index + 1) {
+                Some(value) => value.clone(),
+                None => exit_with_error(&format!("Missing value after {}", name)),
+            })
+    };
+    let number = |name: &str| {
+        option(name).map(|value| {
+            value
+                .parse::<f64>()
+                .unwrap_or_else(|_| exit_with_error(&format!("Invalid {} {}", name, value)))
+        })
+    };
+
+    let openings = match arguments.first() {
+        Some(path) if !path.starts_with("--") => {
+            load_openings(path).unwrap_or_else(|error| exit_with_error(&error))
+        }
+        _ => vec![parse_opening("").expect("start position")],
+    };
+    let mut first = engine(&option("--first").unwrap_or_else(|| "sunfish".to_string()));
+    let mut second = engine(&option("--second").unwrap_or_else(|| "sunfish".to_string()));
+    let mut limits = SearchLimits {
+        depth: number("--depth").map(|depth| depth as i32),
+        nodes: number("--nodes").map(|nodes| nodes as u32),
+        time: number("--time").map(|millis| Duration::from_millis(millis as u64)),
+        ..SearchLimits::default()
+    };
+    if limits == SearchLimits::default() {
+        limits.time = Some(Duration::from_millis(100));
+    }
+    let games = number("--games").unwrap_or(1000.0) as u32;
+    let max_moves = number("--max-moves").unwrap_or(400.0) as usize;
+    let elo0 = number("--elo0").unwrap_or(0.0);
+    let elo1 = number("--elo1").unwrap_or(10.0);
+    let alpha = number("--alpha").unwrap_or(0.05);
+    let beta = number("--beta").unwrap_or(0.05);
+    let pgn_path = option("--pgn");
+
+    println!("{} against {}", first.name(), second.name());
+    let mut score = MatchScore::default();
+    // Each opening is played twice, with the colours swapped
+    for (index, opening) in openings.iter().cycle().flat_map(|o| [o, o]).enumerate() {
+        if score.games() >= games {
+            break;
+        }
+        let first_is_white = index % 2 == 0;
+        let (white, black) = if first_is_white {
+            (&mut first, &mut second)
+        } else {
+            (&mut second, &mut first)
+        };
+        let (game, result, reason) = play_game(
+            white.as_mut(),
+            black.as_mut(),
+            opening.clone(),
+            limits,
+            max_moves,
+        );
+        score.add(result, first_is_white);
+        if let Some(path) = &pgn_path {
+            let moves = game.moves.len();
+            let pgn = PgnGame {
+                tags: vec![
+                    ("Event".to_string(), "sunfish selfplay".to_string()),
+                    ("Round".to_string(), (index + 1).to_string()),
+                    ("White".to_string(), white.name()),
+                    ("Black".to_string(), black.name()),
+                ],
+                game: game.clone(),
+                result: Some(result),
+                comments: vec![(moves, reason.clone())],
+                ..PgnGame::default()
+            };
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", pgn))
+                .unwrap_or_else(|error| {
+                    exit_with_error(&format!("Can't write {}: {}", path, error))
+                });
+        }
+        println!(
+            "Game {} {} as white {} ({}) after {} moves: {}, llr {:.2}",
+            index + 1,
+            if first_is_white { "first" } else { "second" },
+            result,
+            reason,
+            game.moves.len(),
+            score,
+            score.log_likelihood_ratio(elo0, elo1)
+        );
+        match score.sprt(elo0, elo1, alpha, beta) {
+            SprtResult::AcceptH0 => {
+                println!("H0 accepted, the difference is not {} Elo", elo1);
+                break;
+            }
+            SprtResult::AcceptH1 => {
+                println!("H1 accepted, the difference is more than {} Elo", elo0);
+                break;
+            }
+            SprtResult::Continue => {}
+        }
+    }
+    println!("Final score of {}: {}", first.name(), score);
+}
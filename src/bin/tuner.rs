@@ -0,0 +1,55 @@
+use simplelog::{Config, LevelFilter, SimpleLogger};
+
+use sunfish::parameters::{EvalParameters, DEFAULT_PARAMETERS};
+use sunfish::tuning::{fit_scaling_constant, load_dataset, quiescence_scores, tune};
+
+const USAGE: &str =
+    "Usage: tuner DATASET [--parameters FILE] [--output FILE] [--passes N] [--step N]";
+
+fn exit_with_error(error: &str) -> ! {
+    eprintln!("{}\n{}", error, USAGE);
+    std::process::exit(1);
+}
+
+fn main() {
+    let _ = SimpleLogger::init(LevelFilter::Info, Config::default());
+    let arguments: Vec<String> = std::env::args().skip(1).collect();
+    let option = |name: &str| {
+        arguments
+            .iter()
+            .position(|argument| argument == name)
+            .map(|index| match arguments.get(index + 1) {
+                Some(value) => value.clone(),
+                None => exit_with_error(&format!("Missing value after {}", name)),
+            })
+    };
+    let number = |name: &str, default: usize| match option(name) {
+        Some(value) => value
+            .parse()
+            .unwrap_or_else(|_| exit_with_error(&format!("Invalid {} {}", name, value))),
+        None => default,
+    };
+
+    let dataset = match arguments.first() {
+        Some(dataset) if !dataset.starts_with("--") => dataset,
+        _ => exit_with_error("Missing dataset"),
+    };
+    let output = option("--output").unwrap_or_else(|| "tuned_parameters.toml".to_string());
+    let passes = number("--passes", 100);
+    let step = number("--step", 1) as i32;
+    let parameters = match option("--parameters") {
+        Some(path) => EvalParameters::load(&path).unwrap_or_else(|error| exit_with_error(&error)),
+        None => DEFAULT_PARAMETERS.clone(),
+    };
+
+    let positions = load_dataset(dataset).unwrap_or_else(|error| exit_with_error(&error));
+    println!("Loaded {} positions from {}", positions.len(), dataset);
+    let scores = quiescence_scores(&parameters, &positions);
+    let scaling_constant = fit_scaling_constant(&scores, &positions);
+    println!("Fitted scaling constant {:.3}", scaling_constant);
+
+    let tuned = tune(&parameters, &positions, scaling_constant, passes, step);
+    std::fs::write(&output, tuned.to_string())
+        .unwrap_or_else(|error| exit_with_error(&format!("Can't write {}: {}", output, error)));
+    println!("Wrote tuned parameters to {}", output);
+}
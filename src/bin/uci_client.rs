@@ -1,7 +1,11 @@
 use simplelog::{Config, LevelFilter, WriteLogger};
 use std::fs::OpenOptions;
+use std::sync::Arc;
 
-use sunfish::uci::uci_loop;
+use sunfish::bench::{bench, BENCH_DEPTH};
+use sunfish::parameters::{EvalParameters, DEFAULT_PARAMETERS};
+use sunfish::search::SearchConfig;
+use sunfish::uci::uci_loop_with_parameters;
 
 fn set_global_logger() {
     let file = OpenOptions::new()
@@ -14,5 +18,29 @@ fn set_global_logger() {
 
 fn main() {
     set_global_logger();
-    uci_loop();
+    // uci_client [bench] [--eval-file path/to/parameters.toml]
+    let arguments: Vec<String> = std::env::args().collect();
+    let parameters = match arguments
+        .iter()
+        .position(|argument| argument == "--eval-file")
+    {
+        Some(index) => {
+            let path = arguments
+                .get(index + 1)
+                .expect("Missing path after --eval-file");
+            EvalParameters::load(path).unwrap_or_else(|error| {
+                eprintln!("Failed to load evaluation parameters, {}", error);
+                std::process::exit(1);
+            })
+        }
+        None => DEFAULT_PARAMETERS.clone(),
+    };
+    if arguments.get(1).map(String::as_str) == Some("bench") {
+        println!(
+            "{}",
+            bench(&parameters, SearchConfig::default(), BENCH_DEPTH)
+        );
+        return;
+    }
+    uci_loop_with_parameters(Arc::new(parameters));
 }
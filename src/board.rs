@@ -1,4 +1,6 @@
-use crate::pieces::{Direction, Square};
+use crate::evaluation::{tapered_score, ClassicalEvaluator, Evaluator};
+use crate::parameters::DEFAULT_PARAMETERS;
+use crate::pieces::{Direction, Square, MAX_PHASE};
 use std::fmt::Debug;
 
 pub const PADDING: usize = 2;
@@ -8,12 +10,15 @@ pub const BOARD_SIZE: usize = BOARD_SIDE * BOARD_SIDE;
 pub const A8: usize = BOARD_SIDE * PADDING + PADDING;
 pub const H8: usize = A8 + 7;
 pub const A1: usize = A8 + 7 * BOARD_SIDE;
-const H1: usize = A1 + 7;
+pub const H1: usize = A1 + 7;
 
 #[derive(Clone, Copy, Hash, Eq, PartialEq, Debug)]
 pub struct BoardState {
     pub board: [Square; BOARD_SIZE],
-    pub score: i32,
+    pub score: i32, // tapered_score of the three fields below
+    pub midgame_score: i32,
+    pub endgame_score: i32,
+    pub phase: i32, // MAX_PHASE at the start of the game, 0 with only kings and pawns
     pub my_castling_rights: (bool, bool), // first west, second east
     pub opponent_castling_rights: (bool, bool), // first west, second east
     pub en_passant_position: Option<usize>, // square where I can en passant
@@ -26,6 +31,19 @@ pub fn piece_moves(
     start_position: usize,
 ) -> Vec<usize> {
     let mut reachable_squares: Vec<usize> = Vec::with_capacity(20);
+    for_each_piece_move(board_state, piece_moving, start_position, |end_position| {
+        reachable_squares.push(end_position)
+    });
+    reachable_squares
+}
+
+// Like piece_moves, but calls reach on each square instead of collecting them, to avoid allocations
+pub fn for_each_piece_move(
+    board_state: &BoardState,
+    piece_moving: Square,
+    start_position: usize,
+    mut reach: impl FnMut(usize),
+) {
     for move_direction in piece_moving.moves() {
         for end_position in (1..).map(|k| (start_position as i32 + move_direction * k) as usize) {
             let destination_square = board_state.board[end_position];
@@ -62,7 +80,7 @@ pub fn piece_moves(
             }
 
             // Move is probably fine (TODO except king stuff)
-            reachable_squares.push(end_position);
+            reach(end_position);
 
             // Stop pieces that don't slide
             if piece_moving == Square::MyPawn
@@ -78,7 +96,6 @@ pub fn piece_moves(
             }
         }
     }
-    reachable_squares
 }
 
 pub fn gen_moves(board_state: &BoardState) -> Vec<(usize, usize)> {
@@ -125,6 +142,8 @@ pub fn rotate(board_state: &mut BoardState) {
         board_state.board[BOARD_SIZE - 1 - coordinate] = old_val.swap_color();
     }
     board_state.score = -board_state.score;
+    board_state.midgame_score = -board_state.midgame_score;
+    board_state.endgame_score = -board_state.endgame_score;
     std::mem::swap(
         &mut board_state.my_castling_rights,
         &mut board_state.opponent_castling_rights,
@@ -146,6 +165,9 @@ pub fn nullmove(board_state: &BoardState) -> BoardState {
     BoardState {
         board: new_board,
         score: -board_state.score,
+        midgame_score: -board_state.midgame_score,
+        endgame_score: -board_state.endgame_score,
+        phase: board_state.phase,
         my_castling_rights: board_state.opponent_castling_rights,
         opponent_castling_rights: board_state.my_castling_rights,
         en_passant_position: None,
@@ -153,7 +175,21 @@ pub fn nullmove(board_state: &BoardState) -> BoardState {
     }
 }
 
+// Default evaluator for the board functions, called for single moves all over the place
+pub fn board_evaluator() -> ClassicalEvaluator<'static> {
+    ClassicalEvaluator::without_pawn_hash_table(&DEFAULT_PARAMETERS)
+}
+
 pub fn after_move(board_state: &BoardState, move_: &(usize, usize)) -> BoardState {
+    after_move_with(&board_evaluator(), board_state, move_)
+}
+
+// Like after_move, but updating the score with the given evaluator
+pub fn after_move_with<E: Evaluator>(
+    evaluator: &E,
+    board_state: &BoardState,
+    move_: &(usize, usize),
+) -> BoardState {
     let (start_position, end_position) = *move_;
     let start_square = board_state.board[start_position];
     let mut new_board = board_state.board;
@@ -198,7 +234,7 @@ pub fn after_move(board_state: &BoardState, move_: &(usize, usize)) -> BoardStat
     // Pawn promotion, double move and en passant capture
     if start_square == Square::MyPawn {
         let move_type = end_position as i32 - start_position as i32;
-        if (A8 <= end_position) && (end_position <= H8) {
+        if (A8..=H8).contains(&end_position) {
             new_board[end_position] = Square::MyQueen
         } else if move_type == 2 * Direction::NORTH {
             en_passant_position = Some((start_position as i32 + Direction::NORTH) as usize)
@@ -210,9 +246,13 @@ pub fn after_move(board_state: &BoardState, move_: &(usize, usize)) -> BoardStat
         }
     }
 
+    let (midgame_score, endgame_score, phase) = evaluator.scores_after_move(board_state, move_);
     let mut new_board_state = BoardState {
         board: new_board,
-        score: board_state.score + move_value(board_state, &move_),
+        score: tapered_score(midgame_score, endgame_score, phase),
+        midgame_score,
+        endgame_score,
+        phase,
         my_castling_rights,
         opponent_castling_rights,
         king_passant_position,
@@ -222,95 +262,157 @@ pub fn after_move(board_state: &BoardState, move_: &(usize, usize)) -> BoardStat
     new_board_state
 }
 
-pub fn can_check(board_state: &BoardState, move_: &(usize, usize)) -> bool {
-    let (start_position, end_position) = *move_;
-    let moved_piece = board_state.board[start_position];
-    if !moved_piece.is_my_piece() {
-        panic!();
-    }
-    for reachable_square in piece_moves(board_state, moved_piece, end_position) {
-        if board_state.board[reachable_square] == Square::OpponentKing {
-            return true;
+// Change in score caused by the move with the default evaluator, see Evaluator::move_value
+pub fn move_value(board_state: &BoardState, move_: &(usize, usize)) -> i32 {
+    board_evaluator().move_value(board_state, move_)
+}
+
+// Score of the board with the default evaluator, see Evaluator::static_score
+pub fn static_score(board: [Square; BOARD_SIZE]) -> i32 {
+    board_evaluator().static_score(&board)
+}
+
+fn offset(position: usize, direction: i32) -> usize {
+    (position as i32 + direction) as usize
+}
+
+// First piece met sliding from the target along the directions, if it's the given one
+fn slider_attacker(
+    board: &[Square; BOARD_SIZE],
+    target: usize,
+    directions: &[i32],
+    piece: Square,
+) -> Option<usize> {
+    for &direction in directions {
+        let mut position = offset(target, direction);
+        while board[position] == Square::Empty {
+            position = offset(position, direction);
+        }
+        if board[position] == piece {
+            return Some(position);
         }
     }
-    false
+    None
 }
 
-pub fn move_value(board_state: &BoardState, move_: &(usize, usize)) -> i32 {
-    let (start_position, end_position) = *move_;
-    let moving_piece = board_state.board[start_position];
-    if !moving_piece.is_my_piece() {
-        panic!();
-    }
+// Position of the least valuable of my (or the opponent's) pieces attacking the target.
+// Apart from pawns pieces move the same way in both directions, so we look for them with
+// their own moves starting from the target
+pub fn least_valuable_attacker(
+    board: &[Square; BOARD_SIZE],
+    target: usize,
+    mine: bool,
+) -> Option<usize> {
+    let side = |piece: Square| if mine { piece } else { piece.swap_color() };
 
-    // Actual move
-    let mut temp_score =
-        moving_piece.midgame_value(end_position) - moving_piece.midgame_value(start_position);
-
-    // Score for captures
-    if board_state.board[end_position].is_opponent_piece() {
-        // Add to the board score the value of the captured piece in the rotated board
-        temp_score += board_state.board[end_position]
-            .swap_color()
-            .midgame_value(BOARD_SIZE - 1 - end_position);
+    // My pawns capture going north, so they attack from the south
+    let pawn_direction = if mine {
+        Direction::SOUTH
+    } else {
+        Direction::NORTH
+    };
+    for &side_direction in [Direction::WEST, Direction::EAST].iter() {
+        let position = offset(target, pawn_direction + side_direction);
+        if board[position] == side(Square::MyPawn) {
+            return Some(position);
+        }
     }
 
-    // Castling check detection
-    match board_state.king_passant_position {
-        None => {}
-        Some(position) => {
-            // If I'm moving to a position the opponent king just passed through while castling, I can capture it
-            // E.g. any of E1, F1, G1 for white short castling, the king_passant_position would be F1
-            if (end_position as i32 - position as i32).abs() < 2 {
-                temp_score += Square::MyKing.midgame_value(BOARD_SIZE - 1 - end_position);
-            }
+    for &direction in Square::MyKnight.moves() {
+        let position = offset(target, direction);
+        if board[position] == side(Square::MyKnight) {
+            return Some(position);
         }
     }
 
-    // Wierd pawn and king stuff (castling, promotions and en passant)
-    match moving_piece {
-        Square::MyKing => {
-            // Castling, update the score with the new rook position
-            if (end_position as i32 - start_position as i32).abs() == 2 {
-                temp_score += Square::MyRook.midgame_value((start_position + end_position) / 2);
-                temp_score -= Square::MyRook.midgame_value(if end_position < start_position {
-                    A1
-                } else {
-                    H1
-                });
-            }
-        }
-        Square::MyPawn => {
-            if A8 <= end_position && end_position <= H8 {
-                //Promotion
-                temp_score += Square::MyQueen.midgame_value(end_position)
-                    - Square::MyPawn.midgame_value(end_position) //Always promote to queen
-            } else if board_state.en_passant_position == Some(end_position) {
-                //Capture a pawn en passant
-                // TODO explain
-                temp_score +=
-                    Square::MyPawn.midgame_value(BOARD_SIZE - 1 - (end_position + BOARD_SIDE))
-            }
-        }
-        _ => {}
+    slider_attacker(
+        board,
+        target,
+        Square::MyBishop.moves(),
+        side(Square::MyBishop),
+    )
+    .or_else(|| slider_attacker(board, target, Square::MyRook.moves(), side(Square::MyRook)))
+    .or_else(|| {
+        slider_attacker(
+            board,
+            target,
+            Square::MyQueen.moves(),
+            side(Square::MyQueen),
+        )
+    })
+    .or_else(|| {
+        Square::MyKing
+            .moves()
+            .iter()
+            .map(|&direction| offset(target, direction))
+            .find(|&position| board[position] == side(Square::MyKing))
+    })
+}
+
+// Whether my king (or the opponent's, if mine is false) is attacked
+fn is_king_attacked(board: &[Square; BOARD_SIZE], mine: bool) -> bool {
+    let king = if mine {
+        Square::MyKing
+    } else {
+        Square::OpponentKing
+    };
+    // My king is usually at the bottom of the board, the opponent's at the top
+    let king_position = if mine {
+        board.iter().rposition(|&square| square == king)
+    } else {
+        board.iter().position(|&square| square == king)
+    };
+    king_position
+        .is_some_and(|king_position| least_valuable_attacker(board, king_position, !mine).is_some())
+}
+
+// Whether the opponent could capture my king
+pub fn is_in_check(board_state: &BoardState) -> bool {
+    is_king_attacked(&board_state.board, true)
+}
+
+// Whether I could capture the opponent king, meaning the last move was illegal
+pub fn can_capture_king(board_state: &BoardState) -> bool {
+    is_king_attacked(&board_state.board, false)
+}
+
+// Whether the move doesn't leave my king in check, nor castles out of or through check
+pub fn is_legal(board_state: &BoardState, move_: &(usize, usize)) -> bool {
+    let (start_position, end_position) = *move_;
+    if board_state.board[start_position] == Square::MyKing
+        && (start_position as i32 - end_position as i32).abs() == 2
+        && (is_in_check(board_state)
+            || least_valuable_attacker(
+                &board_state.board,
+                (start_position + end_position) / 2,
+                false,
+            )
+            .is_some())
+    {
+        return false;
     }
-    temp_score
+    !can_capture_king(&after_move(board_state, move_))
 }
 
-pub fn static_score(board: [Square; BOARD_SIZE]) -> i32 {
-    board
-        .iter()
-        .enumerate()
-        .map(|(index, piece)| {
-            if piece.is_my_piece() {
-                piece.midgame_value(index)
-            } else if piece.is_opponent_piece() {
-                -piece.swap_color().midgame_value(BOARD_SIZE - 1 - index)
-            } else {
-                0
-            }
-        })
-        .sum()
+pub fn legal_moves(board_state: &BoardState) -> Vec<(usize, usize)> {
+    gen_moves(board_state)
+        .into_iter()
+        .filter(|move_| is_legal(board_state, move_))
+        .collect()
+}
+
+pub fn can_check(board_state: &BoardState, move_: &(usize, usize)) -> bool {
+    let (start_position, end_position) = *move_;
+    let moved_piece = board_state.board[start_position];
+    if !moved_piece.is_my_piece() {
+        panic!();
+    }
+    for reachable_square in piece_moves(board_state, moved_piece, end_position) {
+        if board_state.board[reachable_square] == Square::OpponentKing {
+            return true;
+        }
+    }
+    false
 }
 
 const INITIAL_BOARD: [Square; BOARD_SIZE] = [
@@ -473,6 +575,9 @@ const INITIAL_BOARD: [Square; BOARD_SIZE] = [
 pub const INITIAL_BOARD_STATE: BoardState = BoardState {
     board: INITIAL_BOARD,
     score: 0,
+    midgame_score: 0,
+    endgame_score: 0,
+    phase: MAX_PHASE,
     my_castling_rights: (true, true),
     opponent_castling_rights: (true, true),
     en_passant_position: None,
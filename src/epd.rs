@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use crate::board::BoardState;
+use crate::search::SearchLimits;
+use crate::ui::{from_fen, parse_san};
+
+// Extended Position Description: the first four FEN fields followed by operations like
+// bm Qd1+; id "WAC.001";
+// See https://www.chessprogramming.org/Extended_Position_Description
+pub struct EpdPosition {
+    pub id: String,
+    pub fen: String,
+    pub board_state: BoardState,
+    pub am_black: bool,
+    pub best_moves: Vec<(usize, usize)>, // bm, any of them solves the position
+    pub avoid_moves: Vec<(usize, usize)>, // am, none of them must be played
+    pub comment: String,                 // c0
+    pub limits: SearchLimits,            // acd, acn and acs, the default if none of them
+}
+
+impl EpdPosition {
+    // Move from the point of view of the side to move, like the searcher returns it
+    pub fn is_solved_by(&self, move_: &(usize, usize)) -> bool {
+        (self.best_moves.is_empty() || self.best_moves.contains(move_))
+            && !self.avoid_moves.contains(move_)
+    }
+}
+
+// Operations are split on semicolons outside of quoted strings
+fn split_operations(operations: &str) -> Vec<(&str, &str)> {
+    let mut split = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (index, c) in operations.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == ';' && !quoted {
+            split.push(&operations[start..index]);
+            start = index + 1;
+        }
+    }
+    split.push(&operations[start..]);
+    split
+        .into_iter()
+        .map(str::trim)
+        .filter(|operation| !operation.is_empty())
+        .map(|operation| operation.split_once(' ').unwrap_or((operation, "")))
+        .collect()
+}
+
+pub fn parse_epd(line: &str) -> Result<EpdPosition, String> {
+    let fields: Vec<&str> = line.trim().splitn(5, ' ').collect();
+    if fields.len() < 4 {
+        return Err(format!("expected at least 4 FEN fields in {}", line));
+    }
+    let fen = format!(
+        "{} {} {} {} 0 1",
+        fields[0], fields[1], fields[2], fields[3]
+    );
+    let am_black = fields[1] == "b";
+    let board_state = from_fen(&fen);
+    let mut position = EpdPosition {
+        id: String::new(),
+        fen,
+        board_state,
+        am_black,
+        best_moves: Vec::new(),
+        avoid_moves: Vec::new(),
+        comment: String::new(),
+        limits: SearchLimits::default(),
+    };
+    let parse_moves = |operands: &str| {
+        operands
+            .split_whitespace()
+            .map(|san| {
+                parse_san(&board_state, am_black, san)
+                    .ok_or_else(|| format!("no legal move {} in {}", san, line.trim()))
+            })
+            .collect::<Result<Vec<_>, String>>()
+    };
+    let parse_number = |opcode: &str, operands: &str| {
+        operands
+            .trim()
+            .parse::<u32>()
+            .map_err(|_| format!("invalid {} {} in {}", opcode, operands, line.trim()))
+    };
+    for (opcode, operands) in split_operations(fields.get(4).unwrap_or(&"")) {
+        match opcode {
+            "id" => position.id = operands.trim_matches('"').to_string(),
+            "bm" => position.best_moves = parse_moves(operands)?,
+            "am" => position.avoid_moves = parse_moves(operands)?,
+            "c0" => position.comment = operands.trim_matches('"').to_string(),
+            "acd" => position.limits.depth = Some(parse_number(opcode, operands)? as i32),
+            "acn" => position.limits.nodes = Some(parse_number(opcode, operands)?),
+            "acs" => {
+                let seconds = parse_number(opcode, operands)?;
+                position.limits.time = Some(Duration::from_secs(seconds.into()))
+            }
+            _ => {} // Comments, analysis data and so on
+        }
+    }
+    Ok(position)
+}
+
+// One position per line, empty lines and lines starting with # are skipped
+pub fn load_epd(path: &str) -> Result<Vec<EpdPosition>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            parse_epd(line).map_err(|error| format!("{}:{}: {}", path, line_number + 1, error))
+        })
+        .collect()
+}
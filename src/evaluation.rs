@@ -0,0 +1,598 @@
+use crate::board::{
+    for_each_piece_move, nullmove, BoardState, A1, A8, BOARD_SIDE, BOARD_SIZE, H1, H8, PADDING,
+};
+use crate::parameters::{piece_index, EvalParameters, DEFAULT_PARAMETERS};
+use crate::pawns::{
+    adjacent_files, pawn_attacks, pawn_bitboards, pawn_bitboards_after_move, side_pawn_structure,
+    PawnHashTable, FILE_A,
+};
+use crate::pieces::{table_index, Direction, Square, MAX_PHASE};
+
+// Evaluation is split in a midgame and an endgame score, interpolated according to the game
+// phase. All three are stored in BoardState and updated incrementally after each move.
+pub trait Evaluator {
+    // Midgame score, endgame score and phase after the move, before rotating the board
+    fn scores_after_move(
+        &self,
+        board_state: &BoardState,
+        move_: &(usize, usize),
+    ) -> (i32, i32, i32);
+
+    // Midgame score, endgame score and phase computed from scratch
+    fn static_scores(&self, board: &[Square; BOARD_SIZE]) -> (i32, i32, i32);
+
+    // Change in score caused by the move, from the point of view of the player moving
+    fn move_value(&self, board_state: &BoardState, move_: &(usize, usize)) -> i32 {
+        let (midgame_score, endgame_score, phase) = self.scores_after_move(board_state, move_);
+        tapered_score(midgame_score, endgame_score, phase) - board_state.score
+    }
+
+    fn static_score(&self, board: &[Square; BOARD_SIZE]) -> i32 {
+        let (midgame_score, endgame_score, phase) = self.static_scores(board);
+        tapered_score(midgame_score, endgame_score, phase)
+    }
+
+    // Terms too expensive to update incrementally, added to the board score at the leaves
+    // of the search
+    fn leaf_score(&self, _board_state: &BoardState) -> i32 {
+        0
+    }
+
+    // Board score plus leaf score
+    fn evaluate(&self, board_state: &BoardState) -> i32 {
+        board_state.score + self.leaf_score(board_state)
+    }
+
+    // Recompute all the scores of the board state, e.g. if it was created with another evaluator
+    fn rescore(&self, board_state: &mut BoardState) {
+        let (midgame_score, endgame_score, phase) = self.static_scores(&board_state.board);
+        board_state.midgame_score = midgame_score;
+        board_state.endgame_score = endgame_score;
+        board_state.phase = phase;
+        board_state.score = tapered_score(midgame_score, endgame_score, phase);
+    }
+}
+
+// Interpolate between the midgame and endgame scores according to the game phase
+pub fn tapered_score(midgame_score: i32, endgame_score: i32, phase: i32) -> i32 {
+    let phase = phase.min(MAX_PHASE); // Can be higher after promotions
+    (midgame_score * phase + endgame_score * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+// Names of the terms of the classical evaluation for one side, in the order of
+// ClassicalEvaluator::side_terms. The first BOARD_TERMS are part of the board score, the others
+// are the leaf terms
+pub const CLASSICAL_TERMS: [&str; 14] = [
+    "Material",
+    "Pawn squares",
+    "Knight squares",
+    "Bishop squares",
+    "Rook squares",
+    "Queen squares",
+    "King squares",
+    "Pawn structure",
+    "King shelter",
+    "King attack",
+    "Mobility",
+    "Rook files",
+    "Bishop pair",
+    "Outposts",
+];
+pub const BOARD_TERMS: usize = 8;
+
+// Material and piece square tables only
+#[derive(Clone, Copy, Debug)]
+pub struct PieceSquareEvaluator<'a> {
+    pub parameters: &'a EvalParameters,
+}
+
+impl Default for PieceSquareEvaluator<'static> {
+    fn default() -> Self {
+        PieceSquareEvaluator::with_parameters(&DEFAULT_PARAMETERS)
+    }
+}
+
+impl<'a> PieceSquareEvaluator<'a> {
+    pub fn with_parameters(parameters: &'a EvalParameters) -> Self {
+        PieceSquareEvaluator { parameters }
+    }
+
+    // Midgame and endgame material of my pieces, then their piece square bonus for each kind
+    // of piece. Kings are left out of the material, both sides always have one
+    pub fn side_terms(&self, board: &[Square; BOARD_SIZE]) -> [(i32, i32); 7] {
+        let parameters = self.parameters;
+        let mut terms = [(0, 0); 7];
+        for (position, &piece) in board.iter().enumerate() {
+            if !piece.is_my_piece() {
+                continue;
+            }
+            let (midgame_value, endgame_value) = (
+                parameters.midgame_piece_value(piece),
+                parameters.endgame_piece_value(piece),
+            );
+            if piece != Square::MyKing {
+                terms[0].0 += midgame_value;
+                terms[0].1 += endgame_value;
+            }
+            let kind = 1 + piece_index(piece);
+            terms[kind].0 += parameters.midgame_value(piece, position) - midgame_value;
+            terms[kind].1 += parameters.endgame_value(piece, position) - endgame_value;
+        }
+        terms
+    }
+}
+
+impl Evaluator for PieceSquareEvaluator<'_> {
+    fn scores_after_move(
+        &self,
+        board_state: &BoardState,
+        move_: &(usize, usize),
+    ) -> (i32, i32, i32) {
+        let parameters = self.parameters;
+        (
+            board_state.midgame_score
+                + move_delta(board_state, move_, |piece, position| {
+                    parameters.midgame_value(piece, position)
+                }),
+            board_state.endgame_score
+                + move_delta(board_state, move_, |piece, position| {
+                    parameters.endgame_value(piece, position)
+                }),
+            phase_after_move(board_state, move_),
+        )
+    }
+
+    fn static_scores(&self, board: &[Square; BOARD_SIZE]) -> (i32, i32, i32) {
+        let parameters = self.parameters;
+        (
+            static_value(board, |piece, position| {
+                parameters.midgame_value(piece, position)
+            }),
+            static_value(board, |piece, position| {
+                parameters.endgame_value(piece, position)
+            }),
+            game_phase(board),
+        )
+    }
+}
+
+// Piece square tables plus pawn structure, king safety and piece activity, the default
+// evaluation
+#[derive(Clone, Debug)]
+pub struct ClassicalEvaluator<'a> {
+    pub piece_squares: PieceSquareEvaluator<'a>,
+    pub pawn_hash_table: PawnHashTable,
+}
+
+impl Default for ClassicalEvaluator<'static> {
+    fn default() -> Self {
+        ClassicalEvaluator::with_parameters(&DEFAULT_PARAMETERS)
+    }
+}
+
+impl<'a> ClassicalEvaluator<'a> {
+    pub fn with_parameters(parameters: &'a EvalParameters) -> Self {
+        ClassicalEvaluator {
+            piece_squares: PieceSquareEvaluator::with_parameters(parameters),
+            pawn_hash_table: PawnHashTable::default(),
+        }
+    }
+
+    // Same evaluation without allocating a pawn hash table, for a few moves at a time like in
+    // board::after_move
+    pub fn without_pawn_hash_table(parameters: &'a EvalParameters) -> Self {
+        ClassicalEvaluator {
+            piece_squares: PieceSquareEvaluator::with_parameters(parameters),
+            pawn_hash_table: PawnHashTable::uncached(),
+        }
+    }
+
+    pub fn pawn_structure(&self, board: &[Square; BOARD_SIZE]) -> (i32, i32) {
+        let (my_pawns, opponent_pawns) = pawn_bitboards(board);
+        self.pawn_hash_table.probe(my_pawns, opponent_pawns)
+    }
+
+    // Midgame and endgame score of each term for my pieces, in the order of CLASSICAL_TERMS.
+    // The evaluation is the difference with the terms of the opponent, tapered separately for
+    // the board and the leaf terms
+    pub fn side_terms(&self, board_state: &BoardState) -> [(i32, i32); 14] {
+        let mut terms = [(0, 0); 14];
+        terms[..7].copy_from_slice(&self.piece_squares.side_terms(&board_state.board));
+        let (my_pawns, opponent_pawns) = pawn_bitboards(&board_state.board);
+        terms[7] = side_pawn_structure(my_pawns, opponent_pawns);
+        terms[BOARD_TERMS..].copy_from_slice(&side_leaf_terms(board_state));
+        terms
+    }
+}
+
+impl Evaluator for ClassicalEvaluator<'_> {
+    fn scores_after_move(
+        &self,
+        board_state: &BoardState,
+        move_: &(usize, usize),
+    ) -> (i32, i32, i32) {
+        let (mut midgame_score, mut endgame_score, phase) =
+            self.piece_squares.scores_after_move(board_state, move_);
+
+        // Pawn structure only changes if a pawn moves or gets captured
+        let (start_position, end_position) = *move_;
+        if board_state.board[start_position] == Square::MyPawn
+            || board_state.board[end_position] == Square::OpponentPawn
+        {
+            let (my_pawns, opponent_pawns) = pawn_bitboards(&board_state.board);
+            let (old_midgame, old_endgame) = self.pawn_hash_table.probe(my_pawns, opponent_pawns);
+            let (my_pawns, opponent_pawns) =
+                pawn_bitboards_after_move(board_state, move_, (my_pawns, opponent_pawns));
+            let (new_midgame, new_endgame) = self.pawn_hash_table.probe(my_pawns, opponent_pawns);
+            midgame_score += new_midgame - old_midgame;
+            endgame_score += new_endgame - old_endgame;
+        }
+        (midgame_score, endgame_score, phase)
+    }
+
+    fn static_scores(&self, board: &[Square; BOARD_SIZE]) -> (i32, i32, i32) {
+        let (midgame_score, endgame_score, phase) = self.piece_squares.static_scores(board);
+        let (pawns_midgame, pawns_endgame) = self.pawn_structure(board);
+        (
+            midgame_score + pawns_midgame,
+            endgame_score + pawns_endgame,
+            phase,
+        )
+    }
+
+    fn leaf_score(&self, board_state: &BoardState) -> i32 {
+        // Same as king_safety plus piece_activity, rotating the board only once
+        let side_scores = |board_state: &BoardState| sum_terms(&side_leaf_terms(board_state));
+        let (my_midgame, my_endgame) = side_scores(board_state);
+        let (opponent_midgame, opponent_endgame) = side_scores(&nullmove(board_state));
+        tapered_score(
+            my_midgame - opponent_midgame,
+            my_endgame - opponent_endgame,
+            board_state.phase,
+        )
+    }
+}
+
+pub fn phase_after_move(board_state: &BoardState, move_: &(usize, usize)) -> i32 {
+    let (start_position, end_position) = *move_;
+    let mut phase = board_state.phase;
+    if board_state.board[end_position].is_opponent_piece() {
+        phase -= board_state.board[end_position].swap_color().phase();
+    }
+    if board_state.board[start_position] == Square::MyPawn && (A8..=H8).contains(&end_position) {
+        phase += Square::MyQueen.phase();
+    }
+    phase
+}
+
+// Change in score caused by the move, according to the given piece square values
+pub fn move_delta(
+    board_state: &BoardState,
+    move_: &(usize, usize),
+    value: impl Fn(Square, usize) -> i32,
+) -> i32 {
+    let (start_position, end_position) = *move_;
+    let moving_piece = board_state.board[start_position];
+    if !moving_piece.is_my_piece() {
+        panic!();
+    }
+
+    // Actual move
+    let mut temp_score = value(moving_piece, end_position) - value(moving_piece, start_position);
+
+    // Score for captures
+    if board_state.board[end_position].is_opponent_piece() {
+        // Add to the board score the value of the captured piece in the rotated board
+        temp_score += value(
+            board_state.board[end_position].swap_color(),
+            BOARD_SIZE - 1 - end_position,
+        );
+    }
+
+    // Castling check detection
+    if let Some(position) = board_state.king_passant_position {
+        // If I'm moving to a position the opponent king just passed through while castling, I can capture it
+        // E.g. any of E1, F1, G1 for white short castling, the king_passant_position would be F1
+        if (end_position as i32 - position as i32).abs() < 2 {
+            temp_score += value(Square::MyKing, BOARD_SIZE - 1 - end_position);
+        }
+    }
+
+    // Wierd pawn and king stuff (castling, promotions and en passant)
+    match moving_piece {
+        // Castling, update the score with the new rook position
+        Square::MyKing if (end_position as i32 - start_position as i32).abs() == 2 => {
+            temp_score += value(Square::MyRook, (start_position + end_position) / 2);
+            temp_score -= value(
+                Square::MyRook,
+                if end_position < start_position {
+                    A1
+                } else {
+                    H1
+                },
+            );
+        }
+        Square::MyPawn => {
+            if (A8..=H8).contains(&end_position) {
+                //Promotion
+                temp_score +=
+                    value(Square::MyQueen, end_position) - value(Square::MyPawn, end_position)
+            //Always promote to queen
+            } else if board_state.en_passant_position == Some(end_position) {
+                //Capture a pawn en passant
+                // TODO explain
+                temp_score += value(Square::MyPawn, BOARD_SIZE - 1 - (end_position + BOARD_SIDE))
+            }
+        }
+        _ => {}
+    }
+    temp_score
+}
+
+// Sum of the piece square values of my pieces minus the opponent's
+pub fn static_value(board: &[Square; BOARD_SIZE], value: impl Fn(Square, usize) -> i32) -> i32 {
+    board
+        .iter()
+        .enumerate()
+        .map(|(index, piece)| {
+            if piece.is_my_piece() {
+                value(*piece, index)
+            } else if piece.is_opponent_piece() {
+                -value(piece.swap_color(), BOARD_SIZE - 1 - index)
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+pub fn game_phase(board: &[Square; BOARD_SIZE]) -> i32 {
+    board
+        .iter()
+        .map(|piece| {
+            if piece.is_opponent_piece() {
+                piece.swap_color().phase()
+            } else if piece.is_my_piece() {
+                piece.phase()
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+// King safety terms, only used in the midgame
+const PAWN_SHIELD: [i32; 2] = [24, 12]; // Own pawn one or two ranks in front of the king
+const SEMI_OPEN_KING_FILE: i32 = 20; // No own pawns on the king file or next to it
+const OPEN_KING_FILE: i32 = 15; // Additional penalty if there are no pawns at all
+const KING_ATTACK_LIMIT: i32 = 600;
+
+// Weight of a piece attacking squares next to the enemy king
+fn attack_weight(piece: Square) -> i32 {
+    match piece {
+        Square::MyKnight | Square::MyBishop => 2,
+        Square::MyRook => 3,
+        Square::MyQueen => 5,
+        _ => 0,
+    }
+}
+
+fn find(board: &[Square; BOARD_SIZE], piece: Square) -> Option<usize> {
+    board.iter().position(|&square| square == piece)
+}
+
+// Midgame score for the pawns protecting my king
+pub fn king_shelter(board: &[Square; BOARD_SIZE]) -> i32 {
+    let king_position = match find(board, Square::MyKing) {
+        Some(position) => position,
+        None => return 0,
+    };
+    let mut score = 0;
+    for file_position in (king_position - 1)..=(king_position + 1) {
+        if board[file_position] == Square::Wall {
+            continue;
+        }
+        for (rank, bonus) in PAWN_SHIELD.iter().enumerate() {
+            let position = file_position as i32 + (rank as i32 + 1) * Direction::NORTH;
+            if board[position as usize] == Square::MyPawn {
+                score += bonus;
+            }
+        }
+        let file =
+            (0..8).map(|rank| board[A8 - PADDING + file_position % BOARD_SIDE + rank * BOARD_SIDE]);
+        if !file.clone().any(|square| square == Square::MyPawn) {
+            score -= SEMI_OPEN_KING_FILE;
+            if !file.clone().any(|square| square == Square::OpponentPawn) {
+                score -= OPEN_KING_FILE;
+            }
+        }
+    }
+    score
+}
+
+// Cheap check to avoid generating the moves of pieces that can't attack the king zone
+fn may_reach_king_zone(piece: Square, position: usize, king_position: usize) -> bool {
+    let row = (position / BOARD_SIDE) as i32 - (king_position / BOARD_SIDE) as i32;
+    let column = (position % BOARD_SIDE) as i32 - (king_position % BOARD_SIDE) as i32;
+    let straight = row.abs() <= 1 || column.abs() <= 1;
+    let diagonal = (row - column).abs() <= 2 || (row + column).abs() <= 2;
+    match piece {
+        Square::MyKnight => row.abs() <= 3 && column.abs() <= 3,
+        Square::MyBishop => diagonal,
+        Square::MyRook => straight,
+        _ => straight || diagonal,
+    }
+}
+
+// Midgame score for my pieces attacking the squares around the opponent king
+pub fn king_attack(board_state: &BoardState) -> i32 {
+    // Without a queen attacks on the king are rarely dangerous
+    if !board_state.board.contains(&Square::MyQueen) {
+        return 0;
+    }
+    let king_position = match find(&board_state.board, Square::OpponentKing) {
+        Some(position) => position,
+        None => return 0,
+    };
+    let king_zone = |position: usize| {
+        let row_distance = (position / BOARD_SIDE) as i32 - (king_position / BOARD_SIDE) as i32;
+        let column_distance = (position % BOARD_SIDE) as i32 - (king_position % BOARD_SIDE) as i32;
+        row_distance.abs() <= 1 && column_distance.abs() <= 1
+    };
+    let mut attackers = 0;
+    let mut attack_units = 0;
+    for (position, &piece) in board_state.board.iter().enumerate() {
+        if attack_weight(piece) == 0 || !may_reach_king_zone(piece, position, king_position) {
+            continue;
+        }
+        let mut attacked_squares = 0;
+        for_each_piece_move(board_state, piece, position, |square| {
+            if king_zone(square) {
+                attacked_squares += 1;
+            }
+        });
+        if attacked_squares > 0 {
+            attackers += 1;
+            attack_units += attack_weight(piece) * attacked_squares;
+        }
+    }
+    // A single attacker is rarely dangerous
+    if attackers < 2 {
+        return 0;
+    }
+    (attack_units * attack_units / 4).min(KING_ATTACK_LIMIT)
+}
+
+// Midgame score for the safety of my king and the attacks on the opponent's
+pub fn side_king_safety(board_state: &BoardState) -> i32 {
+    king_shelter(&board_state.board) + king_attack(board_state)
+}
+
+// Midgame and endgame score for the safety of my king minus the opponent's
+pub fn king_safety(board_state: &BoardState) -> (i32, i32) {
+    let midgame_score = side_king_safety(board_state) - side_king_safety(&nullmove(board_state));
+    (midgame_score, 0)
+}
+
+// Piece activity terms as (midgame, endgame) bonuses, loosely based on Stockfish
+// Mobility bonus for each safe square above or below the usual number for the piece
+const KNIGHT_MOBILITY: (i32, i32) = (8, 8);
+const BISHOP_MOBILITY: (i32, i32) = (7, 7);
+const ROOK_MOBILITY: (i32, i32) = (3, 8);
+const QUEEN_MOBILITY: (i32, i32) = (2, 4);
+const ROOK_ON_SEMI_OPEN_FILE: (i32, i32) = (21, 4);
+const ROOK_ON_OPEN_FILE: (i32, i32) = (47, 25);
+const BISHOP_PAIR: (i32, i32) = (45, 60);
+const KNIGHT_OUTPOST: (i32, i32) = (30, 21);
+const BISHOP_OUTPOST: (i32, i32) = (15, 10);
+
+// Mobility bonus per safe square and usual number of safe squares for the piece
+fn mobility_weight(piece: Square) -> ((i32, i32), i32) {
+    match piece {
+        Square::MyKnight => (KNIGHT_MOBILITY, 4),
+        Square::MyBishop => (BISHOP_MOBILITY, 6),
+        Square::MyRook => (ROOK_MOBILITY, 7),
+        Square::MyQueen => (QUEEN_MOBILITY, 13),
+        _ => ((0, 0), 0),
+    }
+}
+
+// Midgame and endgame score of each piece activity term for my pieces, mobility, rook files,
+// bishop pair and outposts like the last CLASSICAL_TERMS
+pub fn side_piece_activity_terms(board_state: &BoardState) -> [(i32, i32); 4] {
+    let (my_pawns, opponent_pawns) = pawn_bitboards(&board_state.board);
+    // Squares attacked by opponent pawns, computed in the rotated board
+    let unsafe_squares = pawn_attacks(opponent_pawns.reverse_bits()).reverse_bits();
+    let supported_squares = pawn_attacks(my_pawns);
+
+    let mut terms = [(0, 0); 4];
+    let mut add = |term: usize, (midgame, endgame): (i32, i32), count: i32| {
+        terms[term].0 += count * midgame;
+        terms[term].1 += count * endgame;
+    };
+
+    let mut bishops = 0;
+    for (position, &piece) in board_state.board.iter().enumerate() {
+        let (weight, usual_squares) = mobility_weight(piece);
+        if usual_squares == 0 {
+            continue;
+        }
+        let mut safe_squares = 0;
+        for_each_piece_move(board_state, piece, position, |square| {
+            if unsafe_squares & (1 << table_index(square)) == 0 {
+                safe_squares += 1;
+            }
+        });
+        add(0, weight, safe_squares - usual_squares);
+
+        let index = table_index(position);
+        let (row, file) = (index / 8, index % 8);
+        match piece {
+            Square::MyRook => {
+                let same_file = FILE_A << file;
+                if my_pawns & same_file == 0 {
+                    if opponent_pawns & same_file == 0 {
+                        add(1, ROOK_ON_OPEN_FILE, 1);
+                    } else {
+                        add(1, ROOK_ON_SEMI_OPEN_FILE, 1);
+                    }
+                }
+            }
+            Square::MyKnight | Square::MyBishop => {
+                if piece == Square::MyBishop {
+                    bishops += 1;
+                }
+                // From the fourth to the sixth rank, defended by a pawn and out of reach of
+                // opponent pawns
+                let ahead = (1u64 << (8 * row)) - 1;
+                if (2..=4).contains(&row)
+                    && supported_squares & (1 << index) != 0
+                    && opponent_pawns & adjacent_files(file) & ahead == 0
+                {
+                    if piece == Square::MyKnight {
+                        add(3, KNIGHT_OUTPOST, 1);
+                    } else {
+                        add(3, BISHOP_OUTPOST, 1);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if bishops >= 2 {
+        add(2, BISHOP_PAIR, 1);
+    }
+    terms
+}
+
+// Midgame and endgame score for the activity of my pieces
+pub fn side_piece_activity(board_state: &BoardState) -> (i32, i32) {
+    sum_terms(&side_piece_activity_terms(board_state))
+}
+
+// King shelter, king attack and piece activity terms of my pieces, the leaf terms of
+// ClassicalEvaluator
+pub fn side_leaf_terms(board_state: &BoardState) -> [(i32, i32); 6] {
+    let mut terms = [(0, 0); 6];
+    terms[0] = (king_shelter(&board_state.board), 0);
+    terms[1] = (king_attack(board_state), 0);
+    terms[2..].copy_from_slice(&side_piece_activity_terms(board_state));
+    terms
+}
+
+// Midgame and endgame sum of the terms
+pub fn sum_terms(terms: &[(i32, i32)]) -> (i32, i32) {
+    terms.iter().fold(
+        (0, 0),
+        |(midgame_score, endgame_score), (midgame, endgame)| {
+            (midgame_score + midgame, endgame_score + endgame)
+        },
+    )
+}
+
+// Midgame and endgame score for the activity of my pieces minus the opponent's
+pub fn piece_activity(board_state: &BoardState) -> (i32, i32) {
+    let (my_midgame, my_endgame) = side_piece_activity(board_state);
+    let (opponent_midgame, opponent_endgame) = side_piece_activity(&nullmove(board_state));
+    (my_midgame - opponent_midgame, my_endgame - opponent_endgame)
+}
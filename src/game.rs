@@ -0,0 +1,193 @@
+use crate::board::{after_move, is_in_check, legal_moves, BoardState, A8, H8};
+use crate::pieces::Square;
+use crate::ui::{absolute_move, from_fen, render_move, render_san, try_from_fen};
+
+pub const INITIAL_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let result = match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        };
+        write!(f, "{}", result)
+    }
+}
+
+// The moves played from a starting position, with what's needed to tell when the game is over
+#[derive(Clone, Debug)]
+pub struct Game {
+    pub start_fen: String,
+    // From the point of view of the side moving, like the searcher returns them
+    pub moves: Vec<(usize, usize)>,
+    // Position before each move and the current one, with the halfmove clock
+    positions: Vec<(BoardState, u32)>,
+    black_starts: bool,
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::from_fen(INITIAL_FEN)
+    }
+}
+
+// Same pieces, castling rights and en passant square, ignoring the scores
+fn is_repetition(first: &BoardState, second: &BoardState) -> bool {
+    first.board == second.board
+        && first.my_castling_rights == second.my_castling_rights
+        && first.opponent_castling_rights == second.opponent_castling_rights
+        && first.en_passant_position == second.en_passant_position
+}
+
+impl Game {
+    pub fn from_fen(fen: &str) -> Self {
+        let halfmove_clock = fen
+            .split(' ')
+            .nth(4)
+            .and_then(|clock| clock.parse().ok())
+            .unwrap_or(0);
+        Game {
+            start_fen: fen.to_string(),
+            moves: Vec::new(),
+            positions: vec![(from_fen(fen), halfmove_clock)],
+            black_starts: fen.split(' ').nth(1) == Some("b"),
+        }
+    }
+
+    // Rejects the FENs try_from_fen rejects
+    pub fn try_from_fen(fen: &str) -> Result<Self, String> {
+        try_from_fen(fen)?;
+        Ok(Game::from_fen(fen))
+    }
+
+    fn current(&self) -> (BoardState, u32) {
+        *self.positions.last().expect("game without positions")
+    }
+
+    pub fn board_state(&self) -> BoardState {
+        self.current().0
+    }
+
+    // Start position and the positions after each move, e.g. to fill the searcher's tables for
+    // repetitions
+    pub fn positions(&self) -> impl Iterator<Item = &BoardState> {
+        self.positions.iter().map(|(board_state, _)| board_state)
+    }
+
+    pub fn am_black(&self) -> bool {
+        self.black_starts ^ (self.moves.len() % 2 == 1)
+    }
+
+    // Black moves are rotated back to white's point of view
+    pub fn absolute_moves(&self) -> Vec<(usize, usize)> {
+        self.moves
+            .iter()
+            .enumerate()
+            .map(|(index, move_)| absolute_move(move_, self.black_starts ^ (index % 2 == 1)))
+            .collect()
+    }
+
+    // Long algebraic notation, with the queen promotions as UCI expects them
+    pub fn uci_moves(&self) -> Vec<String> {
+        self.moves
+            .iter()
+            .zip(self.positions())
+            .zip(self.absolute_moves())
+            .map(|((move_, board_state), absolute)| {
+                let is_promotion =
+                    (A8..=H8).contains(&move_.1) && board_state.board[move_.0] == Square::MyPawn;
+                render_move(&absolute) + if is_promotion { "q" } else { "" }
+            })
+            .collect()
+    }
+
+    // Standard algebraic notation, for people reading logs and PGN
+    pub fn san_moves(&self) -> Vec<String> {
+        self.moves
+            .iter()
+            .zip(self.positions())
+            .enumerate()
+            .map(|(index, (move_, board_state))| {
+                render_san(board_state, self.black_starts ^ (index % 2 == 1), move_)
+            })
+            .collect()
+    }
+
+    pub fn play(&mut self, move_: &(usize, usize)) -> Result<(), String> {
+        let (board_state, halfmove_clock) = self.current();
+        if !legal_moves(&board_state).contains(move_) {
+            return Err(format!(
+                "illegal move {}",
+                render_move(&absolute_move(move_, self.am_black()))
+            ));
+        }
+        let irreversible = board_state.board[move_.0] == Square::MyPawn
+            || board_state.board[move_.1] != Square::Empty;
+        let halfmove_clock = if irreversible { 0 } else { halfmove_clock + 1 };
+        self.positions
+            .push((after_move(&board_state, move_), halfmove_clock));
+        self.moves.push(*move_);
+        Ok(())
+    }
+
+    pub fn undo(&mut self) -> Option<(usize, usize)> {
+        let move_ = self.moves.pop()?;
+        self.positions.pop();
+        Some(move_)
+    }
+
+    // Result and reason if the game is over
+    pub fn result(&self) -> Option<(GameResult, &'static str)> {
+        let (board_state, halfmove_clock) = self.current();
+        let loss = if self.am_black() {
+            GameResult::WhiteWins
+        } else {
+            GameResult::BlackWins
+        };
+        if legal_moves(&board_state).is_empty() {
+            return Some(if is_in_check(&board_state) {
+                (loss, "checkmate")
+            } else {
+                (GameResult::Draw, "stalemate")
+            });
+        }
+        if halfmove_clock >= 100 {
+            return Some((GameResult::Draw, "fifty move rule"));
+        }
+        // Only positions with the same side to move since the last irreversible move
+        let repetitions = self
+            .positions
+            .iter()
+            .rev()
+            .take(halfmove_clock as usize + 1)
+            .step_by(2)
+            .filter(|(previous, _)| is_repetition(previous, &board_state))
+            .count();
+        if repetitions >= 3 {
+            return Some((GameResult::Draw, "threefold repetition"));
+        }
+        let mut minor_pieces = 0;
+        for square in board_state.board.iter() {
+            match square {
+                Square::Empty | Square::Wall | Square::MyKing | Square::OpponentKing => {}
+                Square::MyKnight
+                | Square::MyBishop
+                | Square::OpponentKnight
+                | Square::OpponentBishop => minor_pieces += 1,
+                _ => return None,
+            }
+        }
+        if minor_pieces <= 1 {
+            return Some((GameResult::Draw, "insufficient material"));
+        }
+        None
+    }
+}
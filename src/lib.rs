@@ -1,6 +1,19 @@
+pub mod bench;
 pub mod board;
+pub mod book;
+pub mod epd;
+pub mod evaluation;
+pub mod game;
+pub mod parameters;
+pub mod pawns;
+pub mod pgn;
 pub mod pieces;
 pub mod search;
+pub mod see;
+pub mod selfplay;
 pub mod tests;
+pub mod trace;
+pub mod tuning;
 pub mod uci;
 pub mod ui;
+pub mod xboard;
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::pieces::{table_index, Square};
+
+// Lowercase names used in parameter files, in the order of the arrays in EvalParameters
+pub const PIECE_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+// Mates are detected from the value of the king, see search::MATE_LOWER
+pub const KING_VALUE: i32 = 32000;
+// Keep the material far from the king value, otherwise losing pieces would look like mates
+const MAX_PIECE_VALUE: i32 = 3000;
+const MAX_TABLE_VALUE: i32 = 1000;
+
+// Material values and piece square tables used by the evaluators, by piece in the order of
+// PIECE_NAMES. Tables go from A8 to H1 from the point of view of the side to move, see
+// pieces::table_index
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvalParameters {
+    pub midgame_piece_values: [i32; 6],
+    pub endgame_piece_values: [i32; 6],
+    pub midgame_tables: [[i32; 64]; 6],
+    pub endgame_tables: [[i32; 64]; 6],
+}
+
+// Values from https://github.com/official-stockfish/Stockfish/blob/05f7d59a9a27d9f8bce8bde4e9fed7ecefeb03b9
+pub static DEFAULT_PARAMETERS: EvalParameters = EvalParameters {
+    // From stockfish /src/types.h#L182
+    midgame_piece_values: [136, 782, 830, 1289, 2529, KING_VALUE],
+    endgame_piece_values: [208, 865, 918, 1378, 2687, KING_VALUE],
+    // From stockfish /src/psqt.cpp#L31
+    midgame_tables: [
+        // Pawn
+        [
+            0, 0, 0, 0, 0, 0, 0, 0, // Last rank, no pawns
+            15, 31, 20, 14, 23, 11, 37, 24, //
+            -1, -3, 15, 26, 1, 10, -7, -9, //
+            8, -1, -5, 13, 24, 11, -10, 3, //
+            -9, -18, 8, 32, 43, 25, -4, -16, //
+            -9, -13, -40, 22, 26, -40, 1, -22, //
+            2, 0, 15, 3, 11, 22, 11, -1, //
+            0, 0, 0, 0, 0, 0, 0, 0, //
+        ],
+        // Knight
+        [
+            -200, -80, -53, -32, -32, -53, -80, -200, //
+            -67, -21, 6, 37, 37, 6, -21, -67, //
+            -11, 28, 63, 55, 55, 63, 28, -11, //
+            -29, 13, 42, 52, 52, 42, 13, -29, //
+            -28, 5, 41, 47, 47, 41, 5, -28, //
+            -64, -20, 4, 19, 19, 4, -20, -64, //
+            -79, -39, -24, -9, -9, -24, -39, -79, //
+            -169, -96, -80, -79, -79, -80, -96, -169, //
+        ],
+        // Bishop
+        [
+            -48, -3, -12, -25, -25, -12, -3, -48, //
+            -21, -19, 10, -6, -6, 10, -19, -21, //
+            -17, 4, -1, 8, 8, -1, 4, -17, //
+            -7, 30, 23, 28, 28, 23, 30, -7, //
+            1, 8, 26, 37, 37, 26, 8, 1, //
+            -8, 24, -3, 15, 15, -3, 24, -8, //
+            -18, 7, 14, 3, 3, 14, 7, -18, //
+            -44, -4, -11, -28, -28, -11, -4, -44, //
+        ],
+        // Rook
+        [
+            -22, -24, -6, 4, 4, -6, -24, -22, //
+            -8, 6, 10, 12, 12, 10, 6, -8, //
+            -24, -4, 4, 10, 10, 4, -4, -24, //
+            -24, -12, -1, 6, 6, -1, -12, -24, //
+            -13, -5, -4, -6, -6, -4, -5, -13, //
+            -21, -7, 3, -1, -1, 3, -7, -21, //
+            -18, -10, -5, 9, 9, -5, -10, -18, //
+            -24, -13, -7, 2, 2, -7, -13, -24, //
+        ],
+        // Queen
+        [
+            -2, -2, 1, -2, -2, 1, -2, -2, //
+            -5, 6, 10, 8, 8, 10, 6, -5, //
+            -4, 10, 6, 8, 8, 6, 10, -4, //
+            0, 14, 12, 5, 5, 12, 14, 0, //
+            4, 5, 9, 8, 8, 9, 5, 4, //
+            -3, 6, 13, 7, 7, 13, 6, -3, //
+            -3, 5, 8, 12, 12, 8, 5, -3, //
+            3, -5, -5, 4, 4, -5, -5, 3, //
+        ],
+        // King
+        [
+            6, 8, 4, 0, 0, 4, 8, 6, //
+            8, 12, 6, 2, 2, 6, 12, 8, //
+            12, 15, 8, 3, 3, 8, 15, 12, //
+            14, 17, 11, 6, 6, 11, 17, 15, //
+            16, 19, 13, 10, 10, 13, 19, 16, //
+            19, 25, 16, 12, 12, 16, 25, 19, //
+            27, 30, 24, 18, 18, 24, 30, 27, //
+            27, 32, 27, 19, 19, 27, 32, 27, //
+        ],
+    ],
+    endgame_tables: [
+        // Stockfish pushes pawns only with passed pawn evaluation, here we also use a simple
+        // bonus for advanced pawns
+        // Pawn
+        [
+            0, 0, 0, 0, 0, 0, 0, 0, // Last rank, no pawns
+            90, 90, 90, 90, 90, 90, 90, 90, //
+            55, 55, 55, 55, 55, 55, 55, 55, //
+            30, 30, 30, 30, 30, 30, 30, 30, //
+            12, 12, 12, 12, 12, 12, 12, 12, //
+            2, 2, 2, 2, 2, 2, 2, 2, //
+            0, 0, 0, 0, 0, 0, 0, 0, //
+            0, 0, 0, 0, 0, 0, 0, 0, //
+        ],
+        // Knight
+        [
+            -98, -89, -53, -16, -16, -53, -89, -98, //
+            -64, -45, -37, 16, 16, -37, -45, -64, //
+            -51, -38, -17, 19, 19, -17, -38, -51, //
+            -41, -20, 4, 35, 35, 4, -20, -41, //
+            -36, 0, 13, 34, 34, 13, 0, -36, //
+            -38, -33, -5, 27, 27, -5, -33, -38, //
+            -70, -56, -15, 6, 6, -15, -56, -70, //
+            -105, -74, -46, -18, -18, -46, -74, -105, //
+        ],
+        // Bishop
+        [
+            -51, -40, -39, -20, -20, -39, -40, -51, //
+            -34, -18, -7, 9, 9, -7, -18, -34, //
+            -26, 2, -4, 11, 11, -4, 2, -26, //
+            -24, -6, -10, 17, 17, -10, -6, -24, //
+            -26, -3, -5, 16, 16, -5, -3, -26, //
+            -18, 0, -7, 13, 13, -7, 0, -18, //
+            -38, -13, -14, 0, 0, -14, -13, -38, //
+            -63, -30, -35, -8, -8, -35, -30, -63, //
+        ],
+        // Rook
+        [
+            12, -6, 13, 7, 7, 13, -6, 12, //
+            1, 2, 17, -8, -8, 17, 2, 1, //
+            3, -2, -10, 7, 7, -10, -2, 3, //
+            -8, 5, 4, -9, -9, 4, 5, -8, //
+            -5, 2, -8, 8, 8, -8, 2, -5, //
+            10, -4, 2, -2, -2, 2, -4, 10, //
+            -10, -7, 1, 0, 0, 1, -7, -10, //
+            -2, -6, -3, -2, -2, -3, -6, -2, //
+        ],
+        // Queen
+        [
+            -75, -52, -43, -36, -36, -43, -52, -75, //
+            -50, -27, -24, -8, -8, -24, -27, -50, //
+            -38, -18, -12, 1, 1, -12, -18, -38, //
+            -29, -6, 9, 21, 21, 9, -6, -29, //
+            -23, -3, 13, 24, 24, 13, -3, -23, //
+            -39, -18, -9, 3, 3, -9, -18, -39, //
+            -55, -31, -22, -4, -4, -22, -31, -55, //
+            -69, -57, -47, -26, -26, -47, -57, -69, //
+        ],
+        // King, not scaled down like the midgame table so that it centralises in endgames
+        [
+            11, 59, 73, 78, 78, 73, 59, 11, //
+            47, 121, 116, 131, 131, 116, 121, 47, //
+            92, 172, 184, 191, 191, 184, 172, 92, //
+            96, 166, 199, 199, 199, 199, 166, 96, //
+            103, 156, 172, 172, 172, 172, 156, 103, //
+            88, 130, 169, 175, 175, 169, 130, 88, //
+            53, 100, 133, 135, 135, 133, 100, 53, //
+            1, 45, 85, 76, 76, 85, 45, 1, //
+        ],
+    ],
+};
+
+// Index of my pieces in the arrays of EvalParameters
+pub fn piece_index(piece: Square) -> usize {
+    match piece {
+        Square::MyPawn => 0,
+        Square::MyKnight => 1,
+        Square::MyBishop => 2,
+        Square::MyRook => 3,
+        Square::MyQueen => 4,
+        Square::MyKing => 5,
+        _ => panic!(),
+    }
+}
+
+impl EvalParameters {
+    pub fn midgame_piece_value(&self, piece: Square) -> i32 {
+        self.midgame_piece_values[piece_index(piece)]
+    }
+
+    pub fn endgame_piece_value(&self, piece: Square) -> i32 {
+        self.endgame_piece_values[piece_index(piece)]
+    }
+
+    // Piece square tables: piece value in different positions
+    pub fn midgame_value(&self, piece: Square, position: usize) -> i32 {
+        let index = piece_index(piece);
+        self.midgame_piece_values[index] + self.midgame_tables[index][table_index(position)]
+    }
+
+    pub fn endgame_value(&self, piece: Square, position: usize) -> i32 {
+        let index = piece_index(piece);
+        self.endgame_piece_values[index] + self.endgame_tables[index][table_index(position)]
+    }
+
+    // Check that the search can work with these values
+    pub fn validate(&self) -> Result<(), String> {
+        let phases = [
+            ("midgame", &self.midgame_piece_values, &self.midgame_tables),
+            ("endgame", &self.endgame_piece_values, &self.endgame_tables),
+        ];
+        for (phase, piece_values, tables) in phases.iter() {
+            for (index, name) in PIECE_NAMES.iter().enumerate() {
+                let value = piece_values[index];
+                if *name == "king" {
+                    if value != KING_VALUE {
+                        return Err(format!("{} king value must be {}", phase, KING_VALUE));
+                    }
+                } else if !(1..=MAX_PIECE_VALUE).contains(&value) {
+                    return Err(format!(
+                        "{} {} value {} is not between 1 and {}",
+                        phase, name, value, MAX_PIECE_VALUE
+                    ));
+                }
+                if let Some(entry) = tables[index]
+                    .iter()
+                    .find(|entry| entry.abs() > MAX_TABLE_VALUE)
+                {
+                    return Err(format!(
+                        "{} {} table value {} is not between -{} and {}",
+                        phase, name, entry, MAX_TABLE_VALUE, MAX_TABLE_VALUE
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Parse and validate parameters in the format written by Display, a small subset of TOML:
+    // a [midgame] and an [endgame] section, each with a value and a 64 entries table per piece
+    pub fn parse(text: &str) -> Result<EvalParameters, String> {
+        let mut entries: HashMap<String, Vec<i32>> = HashMap::new();
+        let mut section = String::new();
+        let mut lines = text.lines().enumerate();
+        while let Some((line_number, line)) = lines.next() {
+            let error = |message: String| format!("line {}: {}", line_number + 1, message);
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_string();
+                if section != "midgame" && section != "endgame" {
+                    return Err(error(format!("unknown section {}", section)));
+                }
+                continue;
+            }
+            let (key, mut value) = match line.find('=') {
+                Some(equals) => (line[..equals].trim(), line[equals + 1..].trim().to_string()),
+                None => return Err(error(format!("expected key = value, found {}", line))),
+            };
+            if section.is_empty() {
+                return Err(error(format!("{} is outside of a section", key)));
+            }
+            // Tables span multiple lines, up to the closing bracket
+            if value.starts_with('[') {
+                while !value.contains(']') {
+                    match lines.next() {
+                        Some((_, next_line)) => {
+                            value.push(' ');
+                            value.push_str(next_line.split('#').next().unwrap());
+                        }
+                        None => return Err(error(format!("unterminated table {}", key))),
+                    }
+                }
+                value = value.trim_start_matches('[').trim_end().to_string();
+                if !value.ends_with(']') {
+                    return Err(error(format!("unexpected text after table {}", key)));
+                }
+                value.pop();
+            }
+            let numbers = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|number| !number.is_empty())
+                .map(|number| number.parse::<i32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| error(format!("invalid number in {}", key)))?;
+            let name = format!("{}.{}", section, key);
+            if entries.insert(name.clone(), numbers).is_some() {
+                return Err(error(format!("duplicate key {}", name)));
+            }
+        }
+
+        let mut parameters = DEFAULT_PARAMETERS.clone();
+        let mut take = |name: String, length: usize| match entries.remove(&name) {
+            Some(numbers) if numbers.len() == length => Ok(numbers),
+            Some(numbers) => Err(format!(
+                "{} has {} values instead of {}",
+                name,
+                numbers.len(),
+                length
+            )),
+            None => Err(format!("missing {}", name)),
+        };
+        for (index, name) in PIECE_NAMES.iter().enumerate() {
+            parameters.midgame_piece_values[index] = take(format!("midgame.{}", name), 1)?[0];
+            parameters.endgame_piece_values[index] = take(format!("endgame.{}", name), 1)?[0];
+            let table = take(format!("midgame.{}_table", name), 64)?;
+            parameters.midgame_tables[index].copy_from_slice(&table);
+            let table = take(format!("endgame.{}_table", name), 64)?;
+            parameters.endgame_tables[index].copy_from_slice(&table);
+        }
+        if let Some(name) = entries.keys().min() {
+            return Err(format!("unknown key {}", name));
+        }
+        parameters.validate()?;
+        Ok(parameters)
+    }
+
+    pub fn load(path: &str) -> Result<EvalParameters, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("can't read {}: {}", path, error))?;
+        let parameters =
+            EvalParameters::parse(&text).map_err(|error| format!("{}: {}", path, error))?;
+        Ok(parameters)
+    }
+}
+
+impl fmt::Display for EvalParameters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# Sunfish evaluation parameters")?;
+        writeln!(
+            f,
+            "# Tables go from a8 to h1, from the point of view of white"
+        )?;
+        let phases = [
+            ("midgame", &self.midgame_piece_values, &self.midgame_tables),
+            ("endgame", &self.endgame_piece_values, &self.endgame_tables),
+        ];
+        for (phase, piece_values, tables) in phases.iter() {
+            writeln!(f, "\n[{}]", phase)?;
+            for (name, value) in PIECE_NAMES.iter().zip(piece_values.iter()) {
+                writeln!(f, "{} = {}", name, value)?;
+            }
+            for (name, table) in PIECE_NAMES.iter().zip(tables.iter()) {
+                writeln!(f, "{}_table = [", name)?;
+                for row in table.chunks(8) {
+                    let row: Vec<String> = row.iter().map(|value| format!("{:4}", value)).collect();
+                    writeln!(f, "   {},", row.join(","))?;
+                }
+                writeln!(f, "]")?;
+            }
+        }
+        Ok(())
+    }
+}
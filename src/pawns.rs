@@ -0,0 +1,196 @@
+use std::cell::RefCell;
+
+use crate::board::{BoardState, A8, BOARD_SIDE, BOARD_SIZE, H8};
+use crate::pieces::{table_index, Square};
+
+const PAWN_HASH_TABLE_BITS: u32 = 14;
+
+// Pawn structure terms as (midgame, endgame) penalties and bonuses
+// Values from the same Stockfish version as the piece square tables, /src/pawns.cpp#L32
+const DOUBLED: (i32, i32) = (11, 56);
+const ISOLATED: (i32, i32) = (5, 15);
+const BACKWARD: (i32, i32) = (9, 24);
+// By rank, from the point of view of the pawn owner
+const PASSED: [(i32, i32); 8] = [
+    (0, 0),
+    (10, 28),
+    (17, 33),
+    (15, 41),
+    (62, 72),
+    (168, 177),
+    (276, 260),
+    (0, 0),
+];
+
+pub const FILE_A: u64 = 0x0101_0101_0101_0101;
+pub const FILE_H: u64 = FILE_A << 7;
+
+// Pawns as 64 bit masks, bit 0 is A8 and bit 63 is H1 like in the piece square tables.
+// Reversing the bits rotates the board, like board::rotate
+pub fn pawn_bitboards(board: &[Square; BOARD_SIZE]) -> (u64, u64) {
+    let mut my_pawns = 0;
+    let mut opponent_pawns = 0;
+    // Called for most moves, so only look at the 64 real squares
+    for row in 0..8 {
+        let rank = &board[A8 + row * BOARD_SIDE..A8 + row * BOARD_SIDE + 8];
+        for (column, square) in rank.iter().enumerate() {
+            match square {
+                Square::MyPawn => my_pawns |= 1 << (8 * row + column),
+                Square::OpponentPawn => opponent_pawns |= 1 << (8 * row + column),
+                _ => {}
+            }
+        }
+    }
+    (my_pawns, opponent_pawns)
+}
+
+// Pawn bitboards after the move, before rotating the board, given the current ones
+pub fn pawn_bitboards_after_move(
+    board_state: &BoardState,
+    move_: &(usize, usize),
+    (mut my_pawns, mut opponent_pawns): (u64, u64),
+) -> (u64, u64) {
+    let (start_position, end_position) = *move_;
+    opponent_pawns &= !(1 << table_index(end_position));
+    if board_state.board[start_position] == Square::MyPawn {
+        my_pawns &= !(1 << table_index(start_position));
+        if !(A8..=H8).contains(&end_position) {
+            my_pawns |= 1 << table_index(end_position);
+        }
+        if board_state.en_passant_position == Some(end_position) {
+            opponent_pawns &= !(1 << table_index(end_position + BOARD_SIDE));
+        }
+    }
+    (my_pawns, opponent_pawns)
+}
+
+// Squares attacked by pawns moving towards bit 0
+pub fn pawn_attacks(pawns: u64) -> u64 {
+    ((pawns & !FILE_A) >> 9) | ((pawns & !FILE_H) >> 7)
+}
+
+pub fn adjacent_files(file: usize) -> u64 {
+    let mut mask = 0;
+    if file > 0 {
+        mask |= FILE_A << (file - 1);
+    }
+    if file < 7 {
+        mask |= FILE_A << (file + 1);
+    }
+    mask
+}
+
+// Midgame and endgame score of the pawns of one side, moving towards bit 0
+pub fn side_pawn_structure(pawns: u64, enemy_pawns: u64) -> (i32, i32) {
+    let mut midgame_score = 0;
+    let mut endgame_score = 0;
+    let mut add = |(midgame, endgame): (i32, i32), sign: i32| {
+        midgame_score += sign * midgame;
+        endgame_score += sign * endgame;
+    };
+
+    for index in (0..64).filter(|index| pawns & (1 << index) != 0) {
+        let (row, file) = (index / 8, index % 8);
+        let ahead = (1u64 << (8 * row)) - 1;
+        let level_or_behind = !ahead;
+        let same_file = FILE_A << file;
+        let neighbours = adjacent_files(file);
+
+        if pawns & same_file & level_or_behind & !(1 << index) != 0 {
+            add(DOUBLED, -1);
+        }
+
+        if pawns & neighbours == 0 {
+            add(ISOLATED, -1);
+        } else if pawns & neighbours & level_or_behind == 0 && row >= 2 {
+            // The square in front is attacked by an enemy pawn and no pawn can defend it
+            let attackers = enemy_pawns & neighbours & (0xFF << (8 * (row - 2)));
+            if attackers != 0 {
+                add(BACKWARD, -1);
+            }
+        }
+
+        if enemy_pawns & (same_file | neighbours) & ahead == 0 && pawns & same_file & ahead == 0 {
+            add(PASSED[7 - row], 1);
+        }
+    }
+    (midgame_score, endgame_score)
+}
+
+// Midgame and endgame score of the pawn structure, from my point of view
+pub fn pawn_structure(my_pawns: u64, opponent_pawns: u64) -> (i32, i32) {
+    let (my_midgame, my_endgame) = side_pawn_structure(my_pawns, opponent_pawns);
+    let (opponent_midgame, opponent_endgame) =
+        side_pawn_structure(opponent_pawns.reverse_bits(), my_pawns.reverse_bits());
+    (my_midgame - opponent_midgame, my_endgame - opponent_endgame)
+}
+
+// Pawn structure changes rarely, so we cache its evaluation in a table indexed by a hash of
+// the pawns only. The table is allocated on first use, so creating an evaluator is cheap
+#[derive(Clone, Debug)]
+pub struct PawnHashTable {
+    table: RefCell<Vec<PawnEntry>>,
+    // Without a table the structure is evaluated on every probe, for short lived evaluators
+    cached: bool,
+}
+
+impl Default for PawnHashTable {
+    fn default() -> Self {
+        PawnHashTable {
+            table: RefCell::new(Vec::new()),
+            cached: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct PawnEntry {
+    my_pawns: u64,
+    opponent_pawns: u64,
+    scores: (i32, i32),
+}
+
+impl PawnHashTable {
+    pub fn uncached() -> Self {
+        PawnHashTable {
+            table: RefCell::new(Vec::new()),
+            cached: false,
+        }
+    }
+
+    pub fn probe(&self, my_pawns: u64, opponent_pawns: u64) -> (i32, i32) {
+        if !self.cached {
+            return pawn_structure(my_pawns, opponent_pawns);
+        }
+        let mut table = self.table.borrow_mut();
+        if table.is_empty() {
+            // Zeroed entries are correct, no pawns means no pawn structure score
+            table.resize(1 << PAWN_HASH_TABLE_BITS, PawnEntry::default());
+        }
+        let index = ((my_pawns ^ opponent_pawns.rotate_left(32))
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            >> (64 - PAWN_HASH_TABLE_BITS)) as usize;
+        let entry = &mut table[index];
+        if entry.my_pawns != my_pawns || entry.opponent_pawns != opponent_pawns {
+            *entry = PawnEntry {
+                my_pawns,
+                opponent_pawns,
+                scores: pawn_structure(my_pawns, opponent_pawns),
+            };
+        }
+        entry.scores
+    }
+
+    // Number of pawn structures currently stored
+    pub fn len(&self) -> usize {
+        self.table
+            .borrow()
+            .iter()
+            .filter(|entry| entry.my_pawns != 0 || entry.opponent_pawns != 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,346 @@
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use crate::game::{Game, GameResult, INITIAL_FEN};
+use crate::ui::parse_san;
+
+// Portable Game Notation, see https://www.chessprogramming.org/Portable_Game_Notation
+// Comments and NAGs are indexed by the number of moves played before them, a variation replaces
+// the move at its index and its game starts with the moves of the main line before it
+#[derive(Clone, Debug, Default)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub game: Game,
+    pub result: Option<GameResult>, // None for *, the game isn't over
+    pub comments: Vec<(usize, String)>,
+    pub nags: Vec<(usize, u32)>,
+    pub variations: Vec<(usize, PgnGame)>,
+}
+
+// The tags every game should have, written first and in this order
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u32),
+    StartVariation,
+    EndVariation,
+    Result(Option<GameResult>),
+    Move(String),
+}
+
+fn parse_result(symbol: &str) -> Option<Option<GameResult>> {
+    match symbol {
+        "1-0" => Some(Some(GameResult::WhiteWins)),
+        "0-1" => Some(Some(GameResult::BlackWins)),
+        "1/2-1/2" => Some(Some(GameResult::Draw)),
+        "*" => Some(None),
+        _ => None,
+    }
+}
+
+fn render_result(result: Option<GameResult>) -> String {
+    result.map_or("*".to_string(), |result| result.to_string())
+}
+
+// Tag pair inside the brackets, like Event "Casual game"
+fn parse_tag(tag: &str) -> Result<Token, String> {
+    let (name, value) = tag
+        .trim()
+        .split_once(' ')
+        .ok_or_else(|| format!("invalid tag [{}]", tag))?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(|| format!("invalid tag [{}]", tag))?;
+    Ok(Token::Tag(
+        name.to_string(),
+        value.replace("\\\"", "\"").replace("\\\\", "\\"),
+    ))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    let mut line_start = true;
+    while let Some((index, c)) = chars.next() {
+        let at_line_start = line_start;
+        line_start = c == '\n';
+        let mut read_until = |end: char| {
+            let start = index + c.len_utf8();
+            for (end_index, c) in chars.by_ref() {
+                if c == end {
+                    return Some(&text[start..end_index]);
+                }
+            }
+            None
+        };
+        match c {
+            _ if c.is_whitespace() => {}
+            // Escaped lines and rest of line comments
+            '%' if at_line_start => {
+                read_until('\n');
+                line_start = true;
+            }
+            ';' => {
+                tokens.push(Token::Comment(
+                    read_until('\n').unwrap_or(&text[index + 1..]).to_string(),
+                ));
+                line_start = true;
+            }
+            '{' => {
+                let comment = read_until('}').ok_or("unterminated comment")?;
+                tokens.push(Token::Comment(comment.to_string()));
+            }
+            '[' => {
+                let mut tag = String::new();
+                let mut quoted = false;
+                let mut escaped = false;
+                loop {
+                    let (_, c) = chars.next().ok_or("unterminated tag")?;
+                    if c == ']' && !quoted {
+                        break;
+                    }
+                    quoted ^= c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    tag.push(c);
+                }
+                tokens.push(parse_tag(&tag)?);
+            }
+            '(' => tokens.push(Token::StartVariation),
+            ')' => tokens.push(Token::EndVariation),
+            _ => {
+                let mut end = text.len();
+                while let Some(&(next_index, next)) = chars.peek() {
+                    if next.is_whitespace() || "{}()[];$".contains(next) {
+                        end = next_index;
+                        break;
+                    }
+                    chars.next();
+                }
+                let symbol = &text[index..end];
+                if let Some(nag) = symbol.strip_prefix('$') {
+                    let nag = nag.parse().map_err(|_| format!("invalid NAG {}", symbol))?;
+                    tokens.push(Token::Nag(nag));
+                    continue;
+                }
+                if let Some(result) = parse_result(symbol) {
+                    tokens.push(Token::Result(result));
+                    continue;
+                }
+                // Move numbers like 12. or 12... can be followed by the move without a space
+                let mut symbol = symbol;
+                let without_number = symbol.trim_start_matches(|c: char| c.is_ascii_digit());
+                if without_number.starts_with('.') {
+                    symbol = without_number.trim_start_matches('.');
+                }
+                // Suffix annotations like e4!? are the same as the first NAGs
+                let san = symbol.trim_end_matches(['!', '?']);
+                if !san.is_empty() {
+                    tokens.push(Token::Move(san.to_string()));
+                }
+                let nag = match &symbol[san.len()..] {
+                    "" => continue,
+                    "!" => 1,
+                    "?" => 2,
+                    "!!" => 3,
+                    "??" => 4,
+                    "!?" => 5,
+                    "?!" => 6,
+                    annotation => return Err(format!("invalid annotation {}", annotation)),
+                };
+                tokens.push(Token::Nag(nag));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// Moves, comments, NAGs and variations until the result, the end of the variation or the tags of
+// the next game
+fn parse_line(
+    tokens: &mut Peekable<IntoIter<Token>>,
+    pgn: &mut PgnGame,
+    in_variation: bool,
+) -> Result<(), String> {
+    loop {
+        if !in_variation && matches!(tokens.peek(), Some(Token::Tag(..))) {
+            return Ok(());
+        }
+        let index = pgn.game.moves.len();
+        match tokens.next() {
+            Some(Token::Move(san)) => {
+                let move_ = parse_san(&pgn.game.board_state(), pgn.game.am_black(), &san)
+                    .ok_or_else(|| {
+                        if san.contains('=') && !san.contains("=Q") {
+                            format!("underpromotion {} is not supported", san)
+                        } else {
+                            format!("illegal or ambiguous move {} after {} moves", san, index)
+                        }
+                    })?;
+                pgn.game.play(&move_)?;
+            }
+            Some(Token::Comment(comment)) => {
+                pgn.comments.push((index, comment.trim().to_string()));
+            }
+            Some(Token::Nag(nag)) => pgn.nags.push((index, nag)),
+            Some(Token::StartVariation) => {
+                let mut variation = PgnGame {
+                    game: pgn.game.clone(),
+                    ..PgnGame::default()
+                };
+                variation
+                    .game
+                    .undo()
+                    .ok_or("variation before the first move")?;
+                parse_line(tokens, &mut variation, true)?;
+                pgn.variations.push((index - 1, variation));
+            }
+            Some(Token::EndVariation) if in_variation => return Ok(()),
+            Some(Token::EndVariation) => return Err("unmatched )".to_string()),
+            Some(Token::Result(_)) | None if in_variation => {
+                return Err("unterminated variation".to_string())
+            }
+            Some(Token::Result(result)) => {
+                pgn.result = result;
+                return Ok(());
+            }
+            None => return Ok(()),
+            Some(Token::Tag(name, _)) => return Err(format!("tag {} inside a variation", name)),
+        }
+    }
+}
+
+// Every game of a PGN file, which may start from the position of its FEN tag
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, String> {
+    let mut tokens = tokenize(text)?.into_iter().peekable();
+    let mut games = Vec::new();
+    while tokens.peek().is_some() {
+        let mut pgn = PgnGame::default();
+        while let Some(Token::Tag(..)) = tokens.peek() {
+            if let Some(Token::Tag(name, value)) = tokens.next() {
+                pgn.tags.push((name, value));
+            }
+        }
+        if let Some(fen) = pgn.tag("FEN") {
+            pgn.game = Game::from_fen(fen);
+        }
+        parse_line(&mut tokens, &mut pgn, false)
+            .map_err(|error| format!("game {}: {}", games.len() + 1, error))?;
+        games.push(pgn);
+    }
+    Ok(games)
+}
+
+pub fn load_pgn(path: &str) -> Result<Vec<PgnGame>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    parse_pgn(&text).map_err(|error| format!("{}: {}", path, error))
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Move numbers, SAN moves and annotations from the move at the index on
+    fn movetext(&self, from: usize, words: &mut Vec<String>) {
+        let fields: Vec<&str> = self.game.start_fen.split(' ').collect();
+        let black_starts = fields.get(1) == Some(&"b");
+        let first_number: usize = fields.get(5).and_then(|n| n.parse().ok()).unwrap_or(1);
+        let comments_at = |index: usize| {
+            self.comments
+                .iter()
+                .filter(move |(at, _)| *at == index)
+                .map(|(_, comment)| format!("{{{}}}", comment.replace('}', "")))
+        };
+        words.extend(comments_at(from));
+        let mut needs_number = true;
+        for (index, san) in self.game.san_moves().into_iter().enumerate().skip(from) {
+            let am_black = black_starts ^ (index % 2 == 1);
+            let number = first_number + (index + black_starts as usize) / 2;
+            // Numbers stay on the same line as their move
+            words.push(if !am_black {
+                format!("{}. {}", number, san)
+            } else if needs_number {
+                format!("{}... {}", number, san)
+            } else {
+                san
+            });
+            needs_number = false;
+            for (_, nag) in self.nags.iter().filter(|(at, _)| *at == index + 1) {
+                words.push(format!("${}", nag));
+            }
+            for comment in comments_at(index + 1) {
+                words.push(comment);
+                needs_number = true;
+            }
+            for (_, variation) in self.variations.iter().filter(|(at, _)| *at == index) {
+                let mut variation_words = Vec::new();
+                variation.movetext(index, &mut variation_words);
+                if let Some(first) = variation_words.first_mut() {
+                    first.insert(0, '(');
+                }
+                if let Some(last) = variation_words.last_mut() {
+                    last.push(')');
+                    needs_number = true;
+                }
+                words.extend(variation_words);
+            }
+        }
+    }
+}
+
+// Export format: the seven tag roster first, then the other tags and the movetext in lines of at
+// most 79 characters
+impl std::fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let escape = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        for (name, default) in SEVEN_TAG_ROSTER {
+            let value = if name == "Result" {
+                render_result(self.result)
+            } else {
+                self.tag(name).unwrap_or(default).to_string()
+            };
+            writeln!(f, "[{} \"{}\"]", name, escape(&value))?;
+        }
+        let is_roster = |name: &str| SEVEN_TAG_ROSTER.iter().any(|(roster, _)| *roster == name);
+        if self.game.start_fen != INITIAL_FEN && self.tag("FEN").is_none() {
+            writeln!(f, "[SetUp \"1\"]")?;
+            writeln!(f, "[FEN \"{}\"]", self.game.start_fen)?;
+        }
+        for (name, value) in self.tags.iter().filter(|(name, _)| !is_roster(name)) {
+            writeln!(f, "[{} \"{}\"]", name, escape(value))?;
+        }
+        writeln!(f)?;
+
+        let mut words = Vec::new();
+        self.movetext(0, &mut words);
+        words.push(render_result(self.result));
+        let mut line = String::new();
+        for word in words {
+            if !line.is_empty() && line.len() + 1 + word.len() > 79 {
+                writeln!(f, "{}", line)?;
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        writeln!(f, "{}", line)
+    }
+}
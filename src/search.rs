@@ -1,10 +1,15 @@
 use log::info;
-use std::cmp::max;
+use std::cmp::{max, min};
 use std::collections::HashMap;
 use std::time::{Duration, Instant};
 
-use crate::board::{after_move, can_check, gen_moves, move_value, nullmove, BoardState};
+use crate::board::{
+    after_move_with, can_capture_king, can_check, gen_moves, is_in_check, legal_moves, nullmove,
+    BoardState, A8, BOARD_SIZE, H8,
+};
+use crate::evaluation::{ClassicalEvaluator, Evaluator};
 use crate::pieces::Square;
+use crate::see::static_exchange;
 
 pub const MATE_UPPER: i32 = 32_000 + 8 * 2529; // TODO move somewhere else, do we need MATE_UPPER?
 pub const MATE_LOWER: i32 = 32_000 - 8 * 2529;
@@ -12,50 +17,554 @@ const TRANSPOSITION_TABLE_SIZE: usize = 1_000_000; // TODO explain, make more sp
 const QUIESCENCE_SEARCH_LIMIT: i32 = 130;
 const EVAL_ROUGHNESS: i32 = 10; // TODO do we need this?
 const STOP_SEARCH: i32 = MATE_UPPER * 101;
+// Quiet move ordering: killer moves of the same ply are tried right after the captures worth
+// searching in QS, then the history bonus is at most half of that
+const KILLER_SLOTS: usize = 2;
+const KILLER_BONUS: i32 = QUIESCENCE_SEARCH_LIMIT;
+const HISTORY_LIMIT: i32 = 1 << 14;
+const HISTORY_SCALE: i32 = 2 * HISTORY_LIMIT / QUIESCENCE_SEARCH_LIMIT;
+// Quiet moves after the first LATE_MOVE_INDEX are searched one ply less, from this depth
+const LATE_MOVE_DEPTH: i32 = 3;
+const LATE_MOVE_INDEX: u32 = 3;
+// Margins per ply of depth left, up to FUTILITY_DEPTH
+const FUTILITY_DEPTH: i32 = 2;
+const FUTILITY_MARGIN: i32 = 250;
+const REVERSE_FUTILITY_MARGIN: i32 = 350;
+// Initial half width of the PVS aspiration windows, doubled after each failure
+const ASPIRATION_WINDOW: i32 = 50;
+// Checks are extended up to this many times the depth of the root, in lines where both sides
+// keep checking the search would never end otherwise
+const CHECK_EXTENSION_PLIES: i32 = 2;
+
+// Root search algorithm, both use the same move ordering and tables
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchAlgorithm {
+    // Bisection with zero window searches, the original sunfish algorithm
+    MtdBi,
+    // Principal variation search with aspiration windows
+    Pvs,
+}
+
+// Optional search features, to compare them against the plain search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchConfig {
+    pub algorithm: SearchAlgorithm,
+    pub late_move_reductions: bool,
+    // Skip quiet moves that can't bring the score up to gamma near the leaves
+    pub futility_pruning: bool,
+    // Return early when the score is far above gamma near the leaves
+    pub reverse_futility_pruning: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            algorithm: SearchAlgorithm::MtdBi,
+            late_move_reductions: true,
+            futility_pruning: true,
+            // Misses the solution of two of the tests::puzzles positions
+            reverse_futility_pruning: false,
+        }
+    }
+}
+
+// When to stop the iterative deepening, limits left to None are ignored. Without a time limit
+// the same search always returns the same move and statistics
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    pub nodes: Option<u32>,
+    pub time: Option<Duration>,
+    // Search only as deep as needed to see a mate in this many moves
+    pub mate: Option<i32>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: i32) -> Self {
+        SearchLimits {
+            depth: Some(depth),
+            ..SearchLimits::default()
+        }
+    }
 
-#[derive(Clone, Copy)]
+    pub fn nodes(nodes: u32) -> Self {
+        SearchLimits {
+            nodes: Some(nodes),
+            ..SearchLimits::default()
+        }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        SearchLimits {
+            time: Some(time),
+            ..SearchLimits::default()
+        }
+    }
+
+    pub fn mate(moves: i32) -> Self {
+        SearchLimits {
+            mate: Some(moves),
+            ..SearchLimits::default()
+        }
+    }
+
+    // Deepest iteration allowed, bounded to avoid infinite recursion in finished games
+    fn max_depth(&self) -> i32 {
+        let mut max_depth = self.depth.unwrap_or(98);
+        if let Some(moves) = self.mate {
+            max_depth = min(max_depth, 2 * moves - 1);
+        }
+        max_depth
+    }
+
+    // Checked at every node, the partial iteration is then thrown away
+    fn is_exceeded(&self, now: &Instant, nodes: u32) -> bool {
+        self.nodes.is_some_and(|max_nodes| nodes > max_nodes)
+            || self.time.is_some_and(|time| now.elapsed() > time)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub struct Entry {
     // pub for debugging TODO refactor
     lower: i32,
     upper: i32,
 }
 
+impl Entry {
+    // Mate scores are saved relative to the position instead of the root, the same position
+    // can be reached at different plies
+    fn saved_at(self, ply: i32) -> Entry {
+        Entry {
+            lower: shift_mate_score(self.lower, ply),
+            upper: shift_mate_score(self.upper, ply),
+        }
+    }
+
+    fn loaded_at(self, ply: i32) -> Entry {
+        self.saved_at(-ply)
+    }
+}
+
+// Mate scores count the plies from the root to the king capture, so shorter mates score
+// higher. The king of the side to move can be captured two plies later at the earliest
+fn king_captured_score(ply: i32) -> i32 {
+    ply - MATE_UPPER
+}
+
+// Number of moves to mate for mate scores
+pub fn mate_moves(score: i32) -> Option<i32> {
+    if score >= MATE_LOWER {
+        Some((MATE_UPPER - score) / 2)
+    } else {
+        None
+    }
+}
+
+fn shift_mate_score(score: i32, plies: i32) -> i32 {
+    if score >= MATE_LOWER {
+        score + plies
+    } else if score <= -MATE_LOWER {
+        score - plies
+    } else {
+        score
+    }
+}
+
 const DEFAULT_ENTRY: Entry = Entry {
     lower: -MATE_UPPER,
     upper: MATE_UPPER,
 };
 
-pub struct Searcher {
+// Counters collected during a single call to search, useful to compare engine changes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SearchStatistics {
+    pub nodes: u32,
+    pub quiescence_nodes: u32,
+    pub table_hits: u32, // score_transposition_table lookups that found an entry
+    pub table_cutoffs: u32, // lookups whose bounds were enough to return immediately
+    pub null_move_cutoffs: u32,
+    pub reverse_futility_cutoffs: u32,
+    pub futility_prunes: u32, // moves skipped by futility pruning
+    pub late_move_reductions: u32,
+    pub late_move_researches: u32, // reduced searches that failed high
+    pub killer_cutoffs: u32,
+    pub beta_cutoffs: u32, // cutoffs from the killer move or from the move loop
+    pub first_move_cutoffs: u32, // beta_cutoffs caused by the first move searched
+    pub selective_depth: i32, // maximum ply reached, including quiescence search
+    pub depth_nodes: u32,  // nodes needed for the last completed depth
+    pub previous_depth_nodes: u32, // nodes needed for the depth before that
+}
+
+impl SearchStatistics {
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        if self.beta_cutoffs == 0 {
+            0.0
+        } else {
+            f64::from(self.first_move_cutoffs) / f64::from(self.beta_cutoffs)
+        }
+    }
+
+    // Ratio between the nodes needed for the last two iterative deepening depths
+    pub fn effective_branching_factor(&self) -> f64 {
+        if self.previous_depth_nodes == 0 {
+            0.0
+        } else {
+            f64::from(self.depth_nodes) / f64::from(self.previous_depth_nodes)
+        }
+    }
+}
+
+impl std::fmt::Display for SearchStatistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "nodes {} qnodes {} tt hits {} tt cutoffs {} null cutoffs {} rfp cutoffs {} \
+             futility prunes {} lmr {} lmr researches {} killer cutoffs {} \
+             first move cutoffs {:.1}% ebf {:.2} seldepth {}",
+            self.nodes,
+            self.quiescence_nodes,
+            self.table_hits,
+            self.table_cutoffs,
+            self.null_move_cutoffs,
+            self.reverse_futility_cutoffs,
+            self.futility_prunes,
+            self.late_move_reductions,
+            self.late_move_researches,
+            self.killer_cutoffs,
+            100.0 * self.first_move_cutoff_rate(),
+            self.effective_branching_factor(),
+            self.selective_depth,
+        )
+    }
+}
+
+// Whether the move loses material by SEE, minus its value and the move
+pub type OrderedMove = (bool, i32, (usize, usize));
+
+// What a frame of an iterative bound search is waiting for from the frame above it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PendingSearch {
+    NullMove,
+    Killer((usize, usize)),
+    // The move and the depth it's searched at, maybe reduced
+    Move((usize, usize), i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FrameStage {
+    NullMove,
+    Killer,
+    Moves,
+    Finish,
+}
+
+// Local variables of one call to bound, so that it can be suspended while its children are
+// searched
+#[derive(Clone, Debug)]
+pub struct BoundFrame {
+    pub board_state: BoardState,
+    pub gamma: i32,
+    pub depth: i32, // with the check extension
+    pub ply: i32,
+    start: BoundStart,
+    stage: FrameStage,
+    best: i32,
+    moves_searched: u32,
+    moves: Option<Vec<OrderedMove>>,
+    move_index: usize,
+    pending: Option<PendingSearch>,
+}
+
+impl BoundFrame {
+    // Move being searched by the next frame on the stack, None for the null move
+    pub fn current_move(&self) -> Option<(usize, usize)> {
+        match self.pending {
+            Some(PendingSearch::Killer(move_)) | Some(PendingSearch::Move(move_, _)) => Some(move_),
+            _ => None,
+        }
+    }
+}
+
+// What a frame asks the driver to do next
+enum FrameStep {
+    Search(Box<BoardState>, i32, i32), // board, gamma and depth of a child frame
+    Return(i32),
+}
+
+// A bound search run with an explicit stack of frames instead of recursion, so that it can
+// be paused and resumed, see Searcher::start_bound and Searcher::resume_bound
+#[derive(Clone, Debug, Default)]
+pub struct BoundSearch {
+    pub stack: Vec<BoundFrame>,
+    pub result: Option<i32>,
+    // Score returned by the frame just popped, for the frame now on top
+    returned: Option<i32>,
+}
+
+impl BoundSearch {
+    // Moves from the root to the position currently searched, from the point of view of the
+    // side moving, None for null moves
+    pub fn path(&self) -> Vec<Option<(usize, usize)>> {
+        self.stack[..self.stack.len().saturating_sub(1)]
+            .iter()
+            .map(BoundFrame::current_move)
+            .collect()
+    }
+}
+
+// State computed at the start of bound, see Searcher::enter_bound
+#[derive(Clone, Copy, Debug)]
+struct BoundStart {
+    depth: i32, // with the check extension
+    entry: Entry,
+    in_check: bool,
+    can_prune: bool,
+}
+
+// Table entry for a fail-soft score searched with the (alpha, beta) window
+fn window_entry(score: i32, alpha: i32, beta: i32) -> Entry {
+    if score >= beta {
+        Entry {
+            lower: score,
+            upper: MATE_UPPER,
+        }
+    } else if score <= alpha {
+        Entry {
+            lower: -MATE_UPPER,
+            upper: score,
+        }
+    } else {
+        Entry {
+            lower: score,
+            upper: score,
+        }
+    }
+}
+
+// Not a capture, a promotion or a check
+fn is_quiet(board_state: &BoardState, move_: &(usize, usize)) -> bool {
+    board_state.board[move_.1] == Square::Empty
+        && !(board_state.board[move_.0] == Square::MyPawn && (A8..=H8).contains(&move_.1))
+        && !can_check(board_state, move_)
+}
+
+// Moves not getting out of check are skipped when in check, except capturing the opponent king
+// which wins even if ours could be captured back
+fn is_skipped_evasion(
+    board_state: &BoardState,
+    move_: &(usize, usize),
+    new_board_state: &BoardState,
+) -> bool {
+    board_state.board[move_.1] != Square::OpponentKing && can_capture_king(new_board_state)
+}
+
+pub struct Searcher<E: Evaluator = ClassicalEvaluator<'static>> {
     pub score_transposition_table: HashMap<(BoardState, i32, bool), Entry>,
     pub move_transposition_table: HashMap<BoardState, (usize, usize)>,
-    pub nodes: u32,
+    // Butterfly table indexed by start and end position of quiet moves causing beta cutoffs
+    pub history: Vec<[i32; BOARD_SIZE]>,
+    // Last quiet moves causing beta cutoffs at each ply, most recent first
+    pub killers: Vec<[Option<(usize, usize)>; KILLER_SLOTS]>,
+    pub stats: SearchStatistics,
+    pub config: SearchConfig,
+    pub evaluator: E,
     now: Instant,
-    duration: Duration,
+    limits: SearchLimits,
+    root_depth: i32,
 }
 
 impl Default for Searcher {
     fn default() -> Self {
+        Searcher::with_evaluator(ClassicalEvaluator::default())
+    }
+}
+
+impl<E: Evaluator> Searcher<E> {
+    pub fn with_evaluator(evaluator: E) -> Self {
         Searcher {
             score_transposition_table: HashMap::with_capacity(TRANSPOSITION_TABLE_SIZE),
             move_transposition_table: HashMap::with_capacity(TRANSPOSITION_TABLE_SIZE),
-            nodes: 0,
+            history: vec![[0; BOARD_SIZE]; BOARD_SIZE],
+            killers: Vec::new(),
+            stats: SearchStatistics::default(),
+            config: SearchConfig::default(),
+            evaluator,
             now: Instant::now(),
-            duration: Duration::new(4, 0),
+            limits: SearchLimits::default(),
+            root_depth: 0,
+        }
+    }
+
+    // The same tables with another evaluator, to score positions with many evaluators without
+    // allocating new tables. They are cleared, their scores depend on the evaluation
+    pub fn with_new_evaluator<F: Evaluator>(mut self, evaluator: F) -> Searcher<F> {
+        self.score_transposition_table.clear();
+        self.move_transposition_table.clear();
+        for values in self.history.iter_mut() {
+            *values = [0; BOARD_SIZE];
+        }
+        self.killers.clear();
+        Searcher {
+            score_transposition_table: self.score_transposition_table,
+            move_transposition_table: self.move_transposition_table,
+            history: self.history,
+            killers: self.killers,
+            stats: SearchStatistics::default(),
+            config: self.config,
+            evaluator,
+            now: self.now,
+            limits: self.limits,
+            root_depth: self.root_depth,
+        }
+    }
+
+    // Not too far from the root. Mate limits search only as deep as a mate in that many moves,
+    // extensions would find longer mates too
+    fn can_extend(&self, ply: i32) -> bool {
+        self.limits.mate.is_none() && ply < CHECK_EXTENSION_PLIES * self.root_depth
+    }
+
+    // Remember a quiet move that caused a beta cutoff, deeper searches count more
+    fn update_quiet_move_ordering(&mut self, move_: &(usize, usize), depth: i32, ply: i32) {
+        let (start_position, end_position) = *move_;
+        self.history[start_position][end_position] += depth * depth;
+        if self.history[start_position][end_position] > HISTORY_LIMIT {
+            for values in self.history.iter_mut() {
+                for value in values.iter_mut() {
+                    *value /= 2;
+                }
+            }
+        }
+
+        let ply = ply as usize;
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; KILLER_SLOTS]);
+        }
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(*move_) {
+            killers.rotate_right(1);
+            killers[0] = Some(*move_);
+        }
+    }
+
+    fn is_killer(&self, move_: &(usize, usize), ply: i32) -> bool {
+        self.killers
+            .get(ply as usize)
+            .is_some_and(|killers| killers.contains(&Some(*move_)))
+    }
+
+    // Ordering bonus of quiet moves, from the killer slots and the history table
+    fn quiet_move_bonus(&self, move_: &(usize, usize), ply: i32) -> i32 {
+        let history_bonus = self.history[move_.0][move_.1] / HISTORY_SCALE;
+        if self.is_killer(move_, ply) {
+            KILLER_BONUS + history_bonus
+        } else {
+            history_bonus
+        }
+    }
+
+    // All the moves sorted by (losing capture, -value), where the value is the evaluation
+    // change plus ordering bonuses for checks and, outside of QS, for killers and history
+    fn ordered_moves(&self, board_state: &BoardState, depth: i32, ply: i32) -> Vec<OrderedMove> {
+        let check_bonus = |m: &(usize, usize)| {
+            if can_check(board_state, m) {
+                QUIESCENCE_SEARCH_LIMIT / 2
+            } else {
+                0
+            }
+        };
+        // Captures losing material go after all the other moves, and are pruned in QS
+        let losing_capture = |m: &(usize, usize)| {
+            board_state.board[m.1].is_opponent_piece() && static_exchange(board_state, m) < 0
+        };
+        let mut move_vals: Vec<_> = gen_moves(board_state)
+            .into_iter()
+            .map(|m| {
+                let mut value = self.evaluator.move_value(board_state, &m) + check_bonus(&m);
+                if depth > 0 && board_state.board[m.1] == Square::Empty {
+                    value += self.quiet_move_bonus(&m, ply);
+                }
+                (losing_capture(&m), -value, m)
+            })
+            .collect();
+        move_vals.sort_unstable();
+        move_vals
+    }
+
+    // Save the move for pv construction and killer heuristic
+    fn save_best_move(
+        &mut self,
+        board_state: &BoardState,
+        move_: &(usize, usize),
+        depth: i32,
+        ply: i32,
+    ) {
+        if self.move_transposition_table.len() >= TRANSPOSITION_TABLE_SIZE {
+            self.move_transposition_table.clear();
+        }
+        self.move_transposition_table.insert(*board_state, *move_);
+        if depth > 0 && board_state.board[move_.1] == Square::Empty {
+            self.update_quiet_move_ordering(move_, depth, ply);
+        }
+    }
+
+    fn save_entry(&mut self, board_state: &BoardState, depth: i32, ply: i32, entry: Entry) {
+        if self.score_transposition_table.len() >= TRANSPOSITION_TABLE_SIZE {
+            self.score_transposition_table.clear();
+        }
+        self.score_transposition_table
+            .insert((*board_state, depth, ply == 0), entry.saved_at(ply));
+    }
+
+    fn load_entry(&mut self, board_state: &BoardState, depth: i32, ply: i32) -> Entry {
+        match self
+            .score_transposition_table
+            .get(&(*board_state, depth, ply == 0))
+        {
+            Some(entry) => {
+                self.stats.table_hits += 1;
+                entry.loaded_at(ply)
+            }
+            None => DEFAULT_ENTRY,
         }
     }
-}
 
-impl Searcher {
-    fn bound(&mut self, board_state: &BoardState, gamma: i32, depth: i32, root: bool) -> i32 {
-        self.nodes += 1;
+    // Start of bound, up to the table lookup and the pruning that don't need to search.
+    // Returns the score if the search can stop here, or the state needed to go on
+    fn enter_bound(
+        &mut self,
+        board_state: &BoardState,
+        gamma: i32,
+        depth: i32,
+        ply: i32,
+    ) -> Result<BoundStart, i32> {
+        let root = ply == 0;
+        self.stats.nodes += 1;
+        if depth <= 0 {
+            self.stats.quiescence_nodes += 1;
+        }
+        self.stats.selective_depth = max(self.stats.selective_depth, ply);
 
         // Sunfish is a king-capture engine, so we should always check if we
         // still have a king. Notice since this is the only termination check,
         // the remaining code has to be comfortable with being mated, stalemated
         // or able to capture the opponent king.
         if board_state.score <= -MATE_LOWER {
-            return -MATE_UPPER;
+            return Err(king_captured_score(ply));
+        }
+
+        if root {
+            self.root_depth = depth;
         }
+        // Search one ply deeper when in check. Not at the root, where search looks up the
+        // score with the depth it asked for
+        let in_check = is_in_check(board_state);
+        let depth = if in_check && depth > 0 && !root && self.can_extend(ply) {
+            depth + 1
+        } else {
+            depth
+        };
 
         // Look into the table if we have already searched this position before.
         // We also need to be sure, that the stored search was over the same
@@ -64,107 +573,69 @@ impl Searcher {
         // for calmness, and so there is no reason to keep different depths in the
         // transposition table.
 
-        let entry = *self
-            .score_transposition_table
-            .get(&(*board_state, max(depth, 0), root))
-            .unwrap_or(&DEFAULT_ENTRY);
+        let entry = self.load_entry(board_state, max(depth, 0), ply);
 
         if entry.lower >= gamma
-            && (!root || self.move_transposition_table.get(board_state).is_some())
+            && (!root || self.move_transposition_table.contains_key(board_state))
         // TODO do this last check before calling root, also remove root parameter
         {
-            return entry.lower;
+            self.stats.table_cutoffs += 1;
+            return Err(entry.lower);
         } else if entry.upper < gamma {
-            return entry.upper;
+            self.stats.table_cutoffs += 1;
+            return Err(entry.upper);
         }
 
-        if self.now.elapsed() > self.duration {
-            return STOP_SEARCH;
+        if self.limits.is_exceeded(&self.now, self.stats.nodes) {
+            return Err(STOP_SEARCH);
         }
 
-        let mut best = -MATE_UPPER;
-        // First try not moving at all
-        if depth > 0
-            && !root
-            // TODO maybe base it on the board score?
-            && (board_state.board.iter().any(|&s| matches!(s, Square::MyRook
-                | Square::MyKnight
-                | Square::MyBishop
-                | Square::MyQueen)))
+        // Pruning near the leaves is only safe away from mate scores
+        let can_prune = !root && !in_check && depth <= FUTILITY_DEPTH && gamma.abs() < MATE_LOWER;
+        if self.config.reverse_futility_pruning
+            && can_prune
+            && depth > 0
+            && board_state.score - REVERSE_FUTILITY_MARGIN * depth >= gamma
         {
-            let score = -self.bound(&nullmove(board_state), 1 - gamma, depth - 3, false);
-            if score == -STOP_SEARCH {
-                return STOP_SEARCH;
-            }
-            best = std::cmp::max(best, score);
-        } else if depth <= 0 {
-            // For QSearch we have a different kind of null-move
-            let score = board_state.score;
-            best = std::cmp::max(best, score);
-        }
-
-        if best <= gamma {
-            if let Some(killer_move) = self.move_transposition_table.get(board_state).copied() {
-                // Then killer move. We search it twice, but the tp will fix things for
-                // us. Note, we don't have to check for legality, since we've already
-                // done it before. Also note that in QS the killer must be a capture,
-                // otherwise we will be non deterministic.
-                if depth > 0 || move_value(board_state, &killer_move) >= QUIESCENCE_SEARCH_LIMIT {
-                    let score = -self.bound(
-                        &after_move(board_state, &killer_move),
-                        1 - gamma,
-                        depth - 1,
-                        false,
-                    );
-                    if score == -STOP_SEARCH {
-                        return STOP_SEARCH;
-                    }
-                    best = std::cmp::max(best, score);
-                    // should I add it again to the move_transposition_table?
-                    // self.move_transposition_table.insert(*board_state, killer_move);
-                }
-            }
+            self.stats.reverse_futility_cutoffs += 1;
+            return Err(board_state.score - REVERSE_FUTILITY_MARGIN * depth);
         }
 
-        if best < gamma {
-            // Then all the other moves
-            let others = gen_moves(board_state);
-            let check_bonus = |m| {
-                if can_check(board_state, m) {
-                    QUIESCENCE_SEARCH_LIMIT / 2
-                } else {
-                    0
-                }
-            };
-            let mut move_vals: Vec<_> = others
-                .iter()
-                .map(|m| (-move_value(board_state, m) - check_bonus(m), m))
-                .collect();
-            move_vals.sort_unstable();
-            for (val, m) in move_vals {
-                if depth > 0
-                    || (-val >= QUIESCENCE_SEARCH_LIMIT && (board_state.score - val > best))
-                {
-                    let score =
-                        -self.bound(&after_move(board_state, m), 1 - gamma, depth - 1, false);
-                    if score == -STOP_SEARCH {
-                        return STOP_SEARCH;
-                    }
-                    best = std::cmp::max(best, score);
-                    if best >= gamma {
-                        // Save the move for pv construction and killer heuristic
-                        if self.move_transposition_table.len() >= TRANSPOSITION_TABLE_SIZE {
-                            self.move_transposition_table.clear();
-                        }
-                        self.move_transposition_table.insert(*board_state, *m);
-                        break;
-                    }
-                } else {
-                    break;
-                }
+        Ok(BoundStart {
+            depth,
+            entry,
+            in_check,
+            can_prune,
+        })
+    }
+
+    // Zero window search, the score is at least gamma if and only if the position is worth it.
+    // Run with an explicit stack of frames, see start_bound
+    pub fn bound(&mut self, board_state: &BoardState, gamma: i32, depth: i32, ply: i32) -> i32 {
+        let mut search = BoundSearch::default();
+        self.push_frame(&mut search, *board_state, gamma, depth, ply);
+        loop {
+            if let Some(score) = self.resume_bound(&mut search, u32::MAX) {
+                return score;
             }
         }
+    }
 
+    // End of bound, after searching the moves: stalemate detection and table update
+    fn finish_bound(
+        &mut self,
+        board_state: &BoardState,
+        gamma: i32,
+        ply: i32,
+        start: BoundStart,
+        mut best: i32,
+    ) -> i32 {
+        let BoundStart {
+            depth,
+            entry,
+            in_check,
+            ..
+        } = start;
         // Stalemate checking is a bit tricky: Say we failed low, because
         // we can't (legally) move and so the (real) score is -infty.
         // At the next depth we are allowed to just return r, -infty <= r < gamma,
@@ -174,123 +645,711 @@ impl Searcher {
         // Thus, if best < gamma and best < 0 we need to double check what we are doing.
         // This doesn't prevent sunfish from making a move that results in stalemate,
         // but only if depth == 1, so that's probably fair enough.
-        // (Btw, at depth 1 we can also mate without realizing.)
+        // Mates at the leaves are found by the quiescence search, which looks at all the
+        // evasions when in check.
         if best < gamma && best < 0 && depth > 0 {
             let is_dead = |pos: BoardState| {
                 gen_moves(&pos)
                     .iter()
-                    .any(|m| move_value(&pos, m) >= MATE_LOWER)
+                    .any(|m| self.evaluator.move_value(&pos, m) >= MATE_LOWER)
             };
             if gen_moves(board_state)
                 .iter()
-                .all(|m| is_dead(after_move(board_state, m)))
+                .all(|m| is_dead(after_move_with(&self.evaluator, board_state, m)))
             {
-                let in_check = is_dead(nullmove(board_state));
-                best = if in_check { -MATE_UPPER } else { 0 };
+                best = if in_check {
+                    king_captured_score(ply + 2)
+                } else {
+                    0
+                };
             }
         }
 
         // Update score_transposition_table
-        if self.score_transposition_table.len() >= TRANSPOSITION_TABLE_SIZE {
-            self.score_transposition_table.clear();
-        }
         if best >= gamma {
-            self.score_transposition_table.insert(
-                (*board_state, depth, root),
-                Entry {
-                    lower: best,
-                    upper: entry.upper,
-                },
-            );
+            let new_entry = Entry {
+                lower: best,
+                upper: entry.upper,
+            };
+            self.save_entry(board_state, depth, ply, new_entry);
         } else if best < gamma {
-            self.score_transposition_table.insert(
-                (*board_state, depth, root),
-                Entry {
-                    lower: entry.lower,
-                    upper: best,
-                },
-            );
+            let new_entry = Entry {
+                lower: entry.lower,
+                upper: best,
+            };
+            self.save_entry(board_state, depth, ply, new_entry);
         }
 
         best
     }
 
-    // Iterative deepening MTD-bi search
-    pub fn search(
+    // Same search as bound, that can be paused. The search is run by resume_bound
+    pub fn start_bound(&mut self, board_state: &BoardState, gamma: i32, depth: i32) -> BoundSearch {
+        let mut search = BoundSearch::default();
+        self.push_frame(&mut search, *board_state, gamma, depth, 0);
+        search
+    }
+
+    // Continue the search for at most max_nodes nodes, returns the score once finished
+    pub fn resume_bound(&mut self, search: &mut BoundSearch, max_nodes: u32) -> Option<i32> {
+        let node_limit = self.stats.nodes.saturating_add(max_nodes);
+        while search.result.is_none() && self.stats.nodes < node_limit {
+            let frame = search
+                .stack
+                .last_mut()
+                .expect("empty stack in unfinished search");
+            let ply = frame.ply;
+            match self.advance_frame(frame, search.returned.take()) {
+                FrameStep::Search(board_state, gamma, depth) => {
+                    self.push_frame(search, *board_state, gamma, depth, ply + 1);
+                }
+                FrameStep::Return(score) => {
+                    search.stack.pop();
+                    self.return_score(search, score);
+                }
+            }
+        }
+        search.result
+    }
+
+    fn push_frame(
         &mut self,
+        search: &mut BoundSearch,
         board_state: BoardState,
-        duration: Duration,
-    ) -> ((usize, usize), i32, i32) {
-        self.nodes = 0;
-        let mut reached_depth;
-        self.now = Instant::now();
-        self.duration = duration;
-        let mut last_move = ((0, 0), 0, 0);
+        gamma: i32,
+        depth: i32,
+        ply: i32,
+    ) {
+        match self.enter_bound(&board_state, gamma, depth, ply) {
+            Ok(start) => search.stack.push(BoundFrame {
+                board_state,
+                gamma,
+                depth: start.depth,
+                ply,
+                start,
+                stage: FrameStage::NullMove,
+                best: king_captured_score(ply + 2),
+                moves_searched: 0,
+                moves: None,
+                move_index: 0,
+                pending: None,
+            }),
+            Err(score) => self.return_score(search, score),
+        }
+    }
 
-        // Bound depth to avoid infinite recursion in finished games
-        for depth in 1..99 {
-            // Realistically will reach depths around 6-12, except endgames
-            let mut lower = -MATE_UPPER;
-            let mut upper = MATE_UPPER;
-            while lower < upper - EVAL_ROUGHNESS {
-                let gamma = (lower + upper + 1) / 2;
-                let score = self.bound(&board_state, gamma, depth, true);
-                if score == STOP_SEARCH {
-                    lower = STOP_SEARCH;
+    fn return_score(&mut self, search: &mut BoundSearch, score: i32) {
+        if search.stack.is_empty() {
+            search.result = Some(score);
+        } else {
+            search.returned = Some(score);
+        }
+    }
+
+    // Run the frame until it needs a child search or it's done
+    fn advance_frame(&mut self, frame: &mut BoundFrame, child_score: Option<i32>) -> FrameStep {
+        let board_state = frame.board_state;
+        let (gamma, depth, ply) = (frame.gamma, frame.depth, frame.ply);
+        let BoundStart {
+            in_check,
+            can_prune,
+            ..
+        } = frame.start;
+
+        if let Some(child_score) = child_score {
+            let score = -child_score;
+            match frame
+                .pending
+                .take()
+                .expect("score for a frame not searching")
+            {
+                PendingSearch::NullMove => {
+                    if score == -STOP_SEARCH {
+                        return FrameStep::Return(STOP_SEARCH);
+                    }
+                    frame.best = max(frame.best, score);
+                    if frame.best >= gamma {
+                        self.stats.null_move_cutoffs += 1;
+                    }
+                }
+                PendingSearch::Killer(killer_move) => {
+                    if score == -STOP_SEARCH {
+                        return FrameStep::Return(STOP_SEARCH);
+                    }
+                    if frame.best < gamma && score >= gamma {
+                        self.stats.killer_cutoffs += 1;
+                        self.stats.beta_cutoffs += 1;
+                        self.stats.first_move_cutoffs += 1;
+                        if depth > 0 && board_state.board[killer_move.1] == Square::Empty {
+                            self.update_quiet_move_ordering(&killer_move, depth, ply);
+                        }
+                    }
+                    frame.moves_searched += 1;
+                    frame.best = max(frame.best, score);
+                }
+                PendingSearch::Move(m, searched_depth) => {
+                    // Search again at full depth if the reduced search looks good enough
+                    if score >= gamma && searched_depth < depth - 1 {
+                        self.stats.late_move_researches += 1;
+                        frame.pending = Some(PendingSearch::Move(m, depth - 1));
+                        let new_board_state = after_move_with(&self.evaluator, &board_state, &m);
+                        return FrameStep::Search(Box::new(new_board_state), 1 - gamma, depth - 1);
+                    }
+                    if score == -STOP_SEARCH {
+                        return FrameStep::Return(STOP_SEARCH);
+                    }
+                    frame.moves_searched += 1;
+                    frame.best = max(frame.best, score);
+                    if frame.best >= gamma {
+                        self.stats.beta_cutoffs += 1;
+                        if frame.moves_searched == 1 {
+                            self.stats.first_move_cutoffs += 1;
+                        }
+                        self.save_best_move(&board_state, &m, depth, ply);
+                        frame.stage = FrameStage::Finish;
+                    }
+                }
+            }
+        }
+
+        loop {
+            match frame.stage {
+                FrameStage::NullMove => {
+                    // First try not moving at all
+                    frame.stage = FrameStage::Killer;
+                    // TODO maybe base it on the board score?
+                    if depth > 0
+                        && ply > 0
+                        && !in_check
+                        && board_state.board.iter().any(|&s| {
+                            matches!(
+                                s,
+                                Square::MyRook
+                                    | Square::MyKnight
+                                    | Square::MyBishop
+                                    | Square::MyQueen
+                            )
+                        })
+                    {
+                        frame.pending = Some(PendingSearch::NullMove);
+                        return FrameStep::Search(
+                            Box::new(nullmove(&board_state)),
+                            1 - gamma,
+                            depth - 3,
+                        );
+                    } else if depth <= 0 && !in_check {
+                        // For QSearch we have a different kind of null-move, when in check we
+                        // have to look at all the evasions instead
+                        let score = self.evaluator.evaluate(&board_state);
+                        frame.best = max(frame.best, score);
+                    }
+                }
+                FrameStage::Killer => {
+                    // Then killer move. We search it twice, but the tp will fix things for
+                    // us. Note, we don't have to check for legality, since we've already
+                    // done it before. Also note that in QS the killer must be a capture,
+                    // otherwise we will be non deterministic.
+                    frame.stage = FrameStage::Moves;
+                    if frame.best <= gamma {
+                        if let Some(killer_move) =
+                            self.move_transposition_table.get(&board_state).copied()
+                        {
+                            if depth > 0
+                                || in_check
+                                || (self.evaluator.move_value(&board_state, &killer_move)
+                                    >= QUIESCENCE_SEARCH_LIMIT
+                                    && static_exchange(&board_state, &killer_move) >= 0)
+                            {
+                                frame.pending = Some(PendingSearch::Killer(killer_move));
+                                let new_board_state =
+                                    after_move_with(&self.evaluator, &board_state, &killer_move);
+                                return FrameStep::Search(
+                                    Box::new(new_board_state),
+                                    1 - gamma,
+                                    depth - 1,
+                                );
+                            }
+                        }
+                    }
+                }
+                FrameStage::Moves => {
+                    // Then all the other moves
+                    if frame.best >= gamma {
+                        frame.stage = FrameStage::Finish;
+                        continue;
+                    }
+                    if frame.moves.is_none() {
+                        frame.moves = Some(self.ordered_moves(&board_state, depth, ply));
+                    }
+                    let moves = frame.moves.as_ref().expect("moves were just generated");
+                    while frame.move_index < moves.len() {
+                        let (losing, val, m) = moves[frame.move_index];
+                        frame.move_index += 1;
+                        if !(depth > 0
+                            || in_check
+                            || (!losing
+                                && -val >= QUIESCENCE_SEARCH_LIMIT
+                                && (board_state.score - val > frame.best)))
+                        {
+                            break;
+                        }
+                        let quiet = depth > 0 && is_quiet(&board_state, &m);
+                        if self.config.futility_pruning
+                            && can_prune
+                            && quiet
+                            && frame.moves_searched > 0
+                        {
+                            // The move can't even get close to gamma, use the estimate as its
+                            // score. Only the evaluation change counts, not the ordering bonuses
+                            let estimate = board_state.score
+                                + self.evaluator.move_value(&board_state, &m)
+                                + FUTILITY_MARGIN * depth;
+                            if estimate < gamma {
+                                self.stats.futility_prunes += 1;
+                                frame.best = max(frame.best, estimate);
+                                continue;
+                            }
+                        }
+                        let new_board_state = after_move_with(&self.evaluator, &board_state, &m);
+                        // Most moves don't get out of check, skip them instead of searching the
+                        // king capture
+                        if in_check && is_skipped_evasion(&board_state, &m, &new_board_state) {
+                            continue;
+                        }
+                        let mut reduced_depth = depth - 1;
+                        if self.config.late_move_reductions
+                            && quiet
+                            && !in_check
+                            && depth >= LATE_MOVE_DEPTH
+                            && frame.moves_searched >= LATE_MOVE_INDEX
+                            && !self.is_killer(&m, ply)
+                        {
+                            self.stats.late_move_reductions += 1;
+                            reduced_depth -= 1;
+                        }
+                        frame.pending = Some(PendingSearch::Move(m, reduced_depth));
+                        return FrameStep::Search(
+                            Box::new(new_board_state),
+                            1 - gamma,
+                            reduced_depth,
+                        );
+                    }
+                    frame.stage = FrameStage::Finish;
+                }
+                FrameStage::Finish => {
+                    let score =
+                        self.finish_bound(&board_state, gamma, ply, frame.start, frame.best);
+                    return FrameStep::Return(score);
+                }
+            }
+        }
+    }
+
+    // Alpha-beta quiescence search, same move selection as bound with depth <= 0
+    fn quiescence(&mut self, board_state: &BoardState, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+        self.stats.nodes += 1;
+        self.stats.quiescence_nodes += 1;
+        self.stats.selective_depth = max(self.stats.selective_depth, ply);
+        if board_state.score <= -MATE_LOWER {
+            return king_captured_score(ply);
+        }
+
+        let entry = self.load_entry(board_state, 0, ply);
+        if entry.lower >= beta || entry.upper <= alpha || entry.lower == entry.upper {
+            self.stats.table_cutoffs += 1;
+            return if entry.lower >= beta {
+                entry.lower
+            } else {
+                entry.upper
+            };
+        }
+
+        if self.limits.is_exceeded(&self.now, self.stats.nodes) {
+            return STOP_SEARCH;
+        }
+
+        let original_alpha = alpha;
+        let in_check = is_in_check(board_state);
+        let mut best = king_captured_score(ply + 2);
+        if !in_check {
+            best = self.evaluator.evaluate(board_state);
+            alpha = max(alpha, best);
+        }
+
+        if best < beta {
+            for (losing, val, m) in self.ordered_moves(board_state, 0, ply) {
+                if !in_check
+                    && (losing || -val < QUIESCENCE_SEARCH_LIMIT || board_state.score - val <= best)
+                {
                     break;
                 }
-                if score >= gamma {
-                    lower = score;
-                } else {
-                    upper = score;
+                let new_board_state = after_move_with(&self.evaluator, board_state, &m);
+                if in_check && is_skipped_evasion(board_state, &m, &new_board_state) {
+                    continue;
+                }
+                let score = -self.quiescence(&new_board_state, -beta, -alpha, ply + 1);
+                if score == -STOP_SEARCH {
+                    return STOP_SEARCH;
+                }
+                best = max(best, score);
+                alpha = max(alpha, score);
+                if best >= beta {
+                    self.stats.beta_cutoffs += 1;
+                    break;
                 }
             }
-            if lower == STOP_SEARCH {
+        }
+
+        self.save_entry(
+            board_state,
+            0,
+            ply,
+            window_entry(best, original_alpha, beta),
+        );
+        best
+    }
+
+    // Fail-soft principal variation search: the first move is searched with the full window,
+    // the others with a zero window and again with the full one if they beat alpha
+    fn principal_variation(
+        &mut self,
+        board_state: &BoardState,
+        mut alpha: i32,
+        beta: i32,
+        depth: i32,
+        ply: i32,
+    ) -> i32 {
+        let root = ply == 0;
+        if board_state.score <= -MATE_LOWER {
+            return king_captured_score(ply);
+        }
+        if root {
+            self.root_depth = depth;
+        }
+        let in_check = is_in_check(board_state);
+        let depth = if in_check && depth > 0 && !root && self.can_extend(ply) {
+            depth + 1
+        } else {
+            depth
+        };
+        if depth <= 0 {
+            return self.quiescence(board_state, alpha, beta, ply);
+        }
+        self.stats.nodes += 1;
+        self.stats.selective_depth = max(self.stats.selective_depth, ply);
+
+        let entry = self.load_entry(board_state, depth, ply);
+        // The root needs its best move
+        if !root && (entry.lower >= beta || entry.upper <= alpha || entry.lower == entry.upper) {
+            self.stats.table_cutoffs += 1;
+            return if entry.lower >= beta {
+                entry.lower
+            } else {
+                entry.upper
+            };
+        }
+
+        if self.limits.is_exceeded(&self.now, self.stats.nodes) {
+            return STOP_SEARCH;
+        }
+
+        let original_alpha = alpha;
+        let pv_node = beta - alpha > 1;
+        let can_prune =
+            !root && !pv_node && !in_check && depth <= FUTILITY_DEPTH && beta.abs() < MATE_LOWER;
+        if self.config.reverse_futility_pruning
+            && can_prune
+            && board_state.score - REVERSE_FUTILITY_MARGIN * depth >= beta
+        {
+            self.stats.reverse_futility_cutoffs += 1;
+            return board_state.score - REVERSE_FUTILITY_MARGIN * depth;
+        }
+
+        if !root
+            && !pv_node
+            && !in_check
+            && board_state.board.iter().any(|&s| {
+                matches!(
+                    s,
+                    Square::MyRook | Square::MyKnight | Square::MyBishop | Square::MyQueen
+                )
+            })
+        {
+            let score = -self.principal_variation(
+                &nullmove(board_state),
+                -beta,
+                1 - beta,
+                depth - 3,
+                ply + 1,
+            );
+            if score == -STOP_SEARCH {
+                return STOP_SEARCH;
+            }
+            if score >= beta {
+                self.stats.null_move_cutoffs += 1;
+                return score;
+            }
+        }
+
+        let mut moves = self.ordered_moves(board_state, depth, ply);
+        // The best move of a previous search goes first
+        if let Some(table_move) = self.move_transposition_table.get(board_state) {
+            if let Some(index) = moves.iter().position(|(_, _, m)| m == table_move) {
+                let table_move = moves.remove(index);
+                moves.insert(0, table_move);
+            }
+        }
+
+        let mut best = king_captured_score(ply + 2);
+        let mut best_move = None;
+        let mut moves_searched = 0;
+        for (_losing, _val, m) in moves {
+            let quiet = is_quiet(board_state, &m);
+            if self.config.futility_pruning && can_prune && quiet && moves_searched > 0 {
+                let estimate = board_state.score
+                    + self.evaluator.move_value(board_state, &m)
+                    + FUTILITY_MARGIN * depth;
+                if estimate <= alpha {
+                    self.stats.futility_prunes += 1;
+                    best = max(best, estimate);
+                    continue;
+                }
+            }
+
+            let new_board_state = after_move_with(&self.evaluator, board_state, &m);
+            if in_check && is_skipped_evasion(board_state, &m, &new_board_state) {
+                continue;
+            }
+            let score = if moves_searched == 0 {
+                -self.principal_variation(&new_board_state, -beta, -alpha, depth - 1, ply + 1)
+            } else {
+                let mut reduced_depth = depth - 1;
+                if self.config.late_move_reductions
+                    && quiet
+                    && !in_check
+                    && depth >= LATE_MOVE_DEPTH
+                    && moves_searched >= LATE_MOVE_INDEX
+                    && !self.is_killer(&m, ply)
+                {
+                    self.stats.late_move_reductions += 1;
+                    reduced_depth -= 1;
+                }
+                let mut score = -self.principal_variation(
+                    &new_board_state,
+                    -alpha - 1,
+                    -alpha,
+                    reduced_depth,
+                    ply + 1,
+                );
+                if score > alpha && reduced_depth < depth - 1 {
+                    self.stats.late_move_researches += 1;
+                    score = -self.principal_variation(
+                        &new_board_state,
+                        -alpha - 1,
+                        -alpha,
+                        depth - 1,
+                        ply + 1,
+                    );
+                }
+                if score > alpha && score < beta {
+                    score = -self.principal_variation(
+                        &new_board_state,
+                        -beta,
+                        -alpha,
+                        depth - 1,
+                        ply + 1,
+                    );
+                }
+                score
+            };
+            if score == -STOP_SEARCH {
+                return STOP_SEARCH;
+            }
+            moves_searched += 1;
+            if score > best {
+                best = score;
+                best_move = Some(m);
+            }
+            alpha = max(alpha, score);
+            if best >= beta {
+                self.stats.beta_cutoffs += 1;
+                if moves_searched == 1 {
+                    self.stats.first_move_cutoffs += 1;
+                }
                 break;
             }
-            let score = self.bound(&board_state, lower, depth, true);
+        }
+
+        // Every move losing the king without being in check is stalemate
+        if best <= -MATE_LOWER && !in_check {
+            let is_stalemate = gen_moves(board_state)
+                .iter()
+                .all(|m| can_capture_king(&after_move_with(&self.evaluator, board_state, m)));
+            if is_stalemate {
+                best = 0;
+            }
+        }
+
+        if let Some(m) = best_move {
+            if best > original_alpha || root {
+                self.save_best_move(board_state, &m, depth, ply);
+            }
+        }
+        self.save_entry(
+            board_state,
+            depth,
+            ply,
+            window_entry(best, original_alpha, beta),
+        );
+        best
+    }
+
+    // Window around the score of the previous depth, widened until the score falls inside
+    fn aspiration_search(&mut self, board_state: &BoardState, depth: i32, guess: i32) -> i32 {
+        let mut window = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = if depth == 1 {
+            (-MATE_UPPER, MATE_UPPER)
+        } else {
+            (guess - window, guess + window)
+        };
+        loop {
+            let score = self.principal_variation(board_state, alpha, beta, depth, 0);
+            if score == STOP_SEARCH {
+                return STOP_SEARCH;
+            }
+            if score <= alpha && alpha > -MATE_UPPER {
+                alpha = max(score - window, -MATE_UPPER);
+            } else if score >= beta && beta < MATE_UPPER {
+                beta = std::cmp::min(score + window, MATE_UPPER);
+            } else {
+                return score;
+            }
+            window *= 2;
+        }
+    }
+
+    // Binary search on the score with zero window searches, returns the lower bound found
+    fn mtd_bi(&mut self, board_state: &BoardState, depth: i32) -> i32 {
+        let mut lower = -MATE_UPPER;
+        let mut upper = MATE_UPPER;
+        // Mate scores are searched exactly, to play the shortest mate
+        while lower < upper - EVAL_ROUGHNESS || (lower >= MATE_LOWER && lower < upper) {
+            let gamma = (lower + upper + 1) / 2;
+            let score = self.bound(board_state, gamma, depth, 0);
+            if score == STOP_SEARCH {
+                return STOP_SEARCH;
+            }
+            if score >= gamma {
+                lower = score;
+            } else {
+                upper = score;
+            }
+        }
+        let score = self.bound(board_state, lower, depth, 0);
+        if score == STOP_SEARCH {
+            return STOP_SEARCH;
+        }
+        self.score_transposition_table
+            .get(&(*board_state, depth, true))
+            .expect("score not in table")
+            .lower
+    }
+
+    // Iterative deepening search with the configured algorithm, until one of the limits is hit.
+    // There's no move to return when the game is over
+    pub fn search(
+        &mut self,
+        mut board_state: BoardState,
+        limits: SearchLimits,
+    ) -> (Option<(usize, usize)>, i32, i32, SearchStatistics) {
+        // The board state might have been scored by a different evaluator
+        self.evaluator.rescore(&mut board_state);
+        self.stats = SearchStatistics::default();
+        for values in self.history.iter_mut() {
+            *values = [0; BOARD_SIZE];
+        }
+        self.killers.clear();
+        let mut reached_depth;
+        self.now = Instant::now();
+        let mut last_move = (None, 0, 0);
+        let mut nodes_before_depth = 0;
+
+        for depth in 1..=max(limits.max_depth(), 1) {
+            // Always complete the first depth, to have a move to return
+            self.limits = if depth == 1 {
+                SearchLimits::default()
+            } else {
+                limits
+            };
+            // Realistically will reach depths around 6-12, except endgames
+            let score = match self.config.algorithm {
+                SearchAlgorithm::MtdBi => self.mtd_bi(&board_state, depth),
+                SearchAlgorithm::Pvs => self.aspiration_search(&board_state, depth, last_move.1),
+            };
             if score == STOP_SEARCH {
                 break;
             }
             reached_depth = depth;
+            self.stats.previous_depth_nodes = self.stats.depth_nodes;
+            self.stats.depth_nodes = self.stats.nodes - nodes_before_depth;
+            nodes_before_depth = self.stats.nodes;
             info!(
                 "Reached depth {: <2} score {: <5} nodes {: <7} time {:?}",
                 depth,
                 score,
-                self.nodes,
+                self.stats.nodes,
                 self.now.elapsed()
             );
 
             // If the game hasn't finished we can retrieve our move from the
-            // transposition table.
-
-            last_move = (
-                *self
-                    .move_transposition_table
-                    .get(&board_state)
-                    .expect("move not in table"),
-                self.score_transposition_table
-                    .get(&(board_state, reached_depth, true))
-                    .expect("score not in table")
-                    .lower,
-                reached_depth,
-            );
+            // transposition table. When every move gets mated none of them failed high, any
+            // legal one will do.
+            let top_move = match self.move_transposition_table.get(&board_state) {
+                Some(move_) => Some(*move_),
+                None => legal_moves(&board_state).first().copied(),
+            };
+            last_move = (top_move, score, reached_depth);
 
-            if self.now.elapsed() > self.duration || score > MATE_LOWER {
-                // Don't waste time if a mate is found
+            // Don't waste time if a mate is found, unless a shorter one could still be found
+            // deeper. Check extensions find some mates before their depth
+            if limits.is_exceeded(&self.now, self.stats.nodes)
+                || mate_moves(score).is_some_and(|moves| depth >= 2 * moves - 1)
+            {
                 break;
             }
         }
 
-        last_move
+        (last_move.0, last_move.1, last_move.2, self.stats)
+    }
+
+    // Exact quiescence search score of the position, e.g. to tune the evaluation
+    pub fn quiescence_score(&mut self, board_state: &BoardState) -> i32 {
+        let mut board_state = *board_state;
+        self.evaluator.rescore(&mut board_state);
+        self.limits = SearchLimits::default();
+        // Same bisection as in search, but without any roughness
+        let mut lower = -MATE_UPPER;
+        let mut upper = MATE_UPPER;
+        while lower < upper {
+            let gamma = (lower + upper + 1) / 2;
+            let score = self.bound(&board_state, gamma, 0, 0);
+            if score >= gamma {
+                lower = score;
+            } else {
+                upper = score;
+            }
+        }
+        lower
     }
 
     // Done to prevent move repetitions
     pub fn set_eval_to_zero(&mut self, board_state: &BoardState) {
+        let mut board_state = *board_state;
+        self.evaluator.rescore(&mut board_state);
         // TODO there's probably a better way
         for depth in 1..30 {
             self.score_transposition_table
-                .insert((*board_state, depth, false), Entry { lower: 0, upper: 0 });
+                .insert((board_state, depth, false), Entry { lower: 0, upper: 0 });
         }
     }
 }
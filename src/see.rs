@@ -0,0 +1,58 @@
+use std::cmp::max;
+
+use crate::board::{least_valuable_attacker, BoardState, A8, BOARD_SIDE, H8};
+use crate::pieces::Square;
+
+// Static exchange evaluation: material won or lost by a capture after all the recaptures on
+// the same square, each side always recapturing with its least valuable piece and being free
+// to stop when recapturing would lose material.
+// See https://www.chessprogramming.org/Static_Exchange_Evaluation
+
+// Midgame piece values, the king is worth the same as in the evaluation so that it's never
+// worth recapturing with it on a defended square
+fn exchange_value(square: Square) -> i32 {
+    match square {
+        Square::Empty | Square::Wall => 0,
+        _ if square.is_opponent_piece() => square.swap_color().midgame_piece_value(),
+        _ => square.midgame_piece_value(),
+    }
+}
+
+// Material won by the move from my point of view, negative for losing captures.
+// Pieces are taken off the board as they capture, so x-ray attackers behind them join in
+pub fn static_exchange(board_state: &BoardState, move_: &(usize, usize)) -> i32 {
+    let (start_position, end_position) = *move_;
+    let mut board = board_state.board;
+    let mut piece = board[start_position];
+    // Material balance after each capture, from the point of view of the side capturing
+    let mut gains = [0; 32];
+    gains[0] = exchange_value(board[end_position]);
+    if piece == Square::MyPawn {
+        if board_state.en_passant_position == Some(end_position) {
+            gains[0] = exchange_value(Square::MyPawn);
+            board[end_position + BOARD_SIDE] = Square::Empty;
+        }
+        if (A8..=H8).contains(&end_position) {
+            gains[0] += exchange_value(Square::MyQueen) - exchange_value(Square::MyPawn);
+            piece = Square::MyQueen;
+        }
+    }
+    board[start_position] = Square::Empty;
+
+    let mut captures = 0;
+    let mut mine = false;
+    while let Some(position) = least_valuable_attacker(&board, end_position, mine) {
+        captures += 1;
+        gains[captures] = exchange_value(piece) - gains[captures - 1];
+        piece = board[position];
+        board[position] = Square::Empty;
+        mine = !mine;
+    }
+
+    // Going back, each side can choose not to recapture
+    while captures > 0 {
+        gains[captures - 1] = -max(-gains[captures - 1], gains[captures]);
+        captures -= 1;
+    }
+    gains[0]
+}
@@ -0,0 +1,285 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Arc;
+
+use crate::evaluation::ClassicalEvaluator;
+use crate::game::{Game, GameResult, INITIAL_FEN};
+use crate::parameters::EvalParameters;
+use crate::search::{SearchConfig, SearchLimits, Searcher};
+use crate::ui::{absolute_move, parse_move};
+
+// Something that can play a game, the engine in this process or another one over UCI
+pub trait Player {
+    fn name(&self) -> String;
+    fn best_move(&mut self, game: &Game, limits: SearchLimits) -> Result<(usize, usize), String>;
+}
+
+pub struct SearcherPlayer {
+    pub name: String,
+    pub parameters: Arc<EvalParameters>,
+    pub config: SearchConfig,
+}
+
+impl Player for SearcherPlayer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    // New tables for each move, like the UCI loop
+    fn best_move(&mut self, game: &Game, limits: SearchLimits) -> Result<(usize, usize), String> {
+        let mut searcher =
+            Searcher::with_evaluator(ClassicalEvaluator::with_parameters(&self.parameters));
+        searcher.config = self.config;
+        for board_state in game.positions().skip(1) {
+            searcher.set_eval_to_zero(board_state);
+        }
+        let (top_move, _score, _depth, _stats) = searcher.search(game.board_state(), limits);
+        top_move.ok_or_else(|| format!("{} has no legal move", self.name))
+    }
+}
+
+pub struct UciPlayer {
+    pub name: String,
+    process: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+}
+
+impl UciPlayer {
+    // Command and arguments separated by spaces
+    pub fn start(command: &str) -> Result<Self, String> {
+        let mut words = command.split_whitespace();
+        let program = words.next().ok_or("empty engine command")?;
+        let mut process = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|error| format!("can't start {}: {}", command, error))?;
+        let input = process.stdin.take().ok_or("no engine input")?;
+        let output = BufReader::new(process.stdout.take().ok_or("no engine output")?);
+        let mut player = UciPlayer {
+            name: command.to_string(),
+            process,
+            input,
+            output,
+        };
+        player.send("uci")?;
+        player.wait_for("uciok")?;
+        Ok(player)
+    }
+
+    pub fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.input, "{}", command)
+            .and_then(|_| self.input.flush())
+            .map_err(|error| format!("can't write to {}: {}", self.name, error))
+    }
+
+    // Skips lines until one starting with the prefix, which is returned
+    fn wait_for(&mut self, prefix: &str) -> Result<String, String> {
+        loop {
+            let mut line = String::new();
+            match self.output.read_line(&mut line) {
+                Ok(0) => return Err(format!("{} exited", self.name)),
+                Ok(_) if line.starts_with(prefix) => return Ok(line.trim_end().to_string()),
+                Ok(_) => {}
+                Err(error) => return Err(format!("can't read from {}: {}", self.name, error)),
+            }
+        }
+    }
+}
+
+impl Drop for UciPlayer {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.process.wait();
+    }
+}
+
+impl Player for UciPlayer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn best_move(&mut self, game: &Game, limits: SearchLimits) -> Result<(usize, usize), String> {
+        let start = if game.start_fen == INITIAL_FEN {
+            "startpos".to_string()
+        } else {
+            format!("fen {}", game.start_fen)
+        };
+        let moves = game.uci_moves();
+        if moves.is_empty() {
+            self.send(&format!("position {}", start))?;
+        } else {
+            self.send(&format!("position {} moves {}", start, moves.join(" ")))?;
+        }
+        let mut go = String::from("go");
+        if let Some(depth) = limits.depth {
+            go += &format!(" depth {}", depth);
+        }
+        if let Some(nodes) = limits.nodes {
+            go += &format!(" nodes {}", nodes);
+        }
+        if let Some(time) = limits.time {
+            go += &format!(" movetime {}", time.as_millis());
+        }
+        self.send(&go)?;
+        let line = self.wait_for("bestmove")?;
+        match line.split(' ').nth(1) {
+            Some(move_) if move_.len() >= 4 && move_.is_ascii() => {
+                Ok(absolute_move(&parse_move(move_), game.am_black()))
+            }
+            _ => Err(format!("{} sent {}", self.name, line)),
+        }
+    }
+}
+
+// Play until the game is over or max_moves moves have been played, which counts as a draw.
+// Errors and illegal moves lose the game
+pub fn play_game(
+    white: &mut dyn Player,
+    black: &mut dyn Player,
+    mut game: Game,
+    limits: SearchLimits,
+    max_moves: usize,
+) -> (Game, GameResult, String) {
+    loop {
+        if let Some((result, reason)) = game.result() {
+            return (game, result, reason.to_string());
+        }
+        if game.moves.len() >= max_moves {
+            return (game, GameResult::Draw, "adjudicated".to_string());
+        }
+        let (best_move, loss) = if game.am_black() {
+            (black.best_move(&game, limits), GameResult::WhiteWins)
+        } else {
+            (white.best_move(&game, limits), GameResult::BlackWins)
+        };
+        let played = best_move.and_then(|move_| game.play(&move_));
+        if let Err(error) = played {
+            return (game, loss, error);
+        }
+    }
+}
+
+// Expected score for an Elo difference
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn elo_difference(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+// Wins, draws and losses of the first player
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MatchScore {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SprtResult {
+    AcceptH0, // the Elo difference is more likely elo0 than elo1
+    AcceptH1, // and the other way around
+    Continue,
+}
+
+impl MatchScore {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn add(&mut self, result: GameResult, first_is_white: bool) {
+        match (result, first_is_white) {
+            (GameResult::Draw, _) => self.draws += 1,
+            (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) => self.wins += 1,
+            _ => self.losses += 1,
+        }
+    }
+
+    fn score_and_variance(&self) -> (f64, f64) {
+        let games = f64::from(self.games());
+        let score = (f64::from(self.wins) + 0.5 * f64::from(self.draws)) / games;
+        let variance = (f64::from(self.wins) * (1.0 - score).powi(2)
+            + f64::from(self.draws) * (0.5 - score).powi(2)
+            + f64::from(self.losses) * score.powi(2))
+            / games;
+        (score, variance)
+    }
+
+    // Elo difference with the 95% confidence interval around it
+    pub fn elo(&self) -> (f64, f64) {
+        let (score, variance) = self.score_and_variance();
+        let margin = 1.96 * (variance / f64::from(self.games())).sqrt();
+        let clamp = |score: f64| score.clamp(1e-6, 1.0 - 1e-6);
+        let elo = elo_difference(clamp(score));
+        let upper = elo_difference(clamp(score + margin));
+        let lower = elo_difference(clamp(score - margin));
+        (elo, (upper - lower) / 2.0)
+    }
+
+    // Log likelihood ratio of the first player being elo1 rather than elo0 stronger, with the
+    // normal approximation of the trinomial distribution
+    pub fn log_likelihood_ratio(&self, elo0: f64, elo1: f64) -> f64 {
+        let (score, variance) = self.score_and_variance();
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (expected_score(elo0), expected_score(elo1));
+        f64::from(self.games()) * (score1 - score0) * (2.0 * score - score0 - score1)
+            / (2.0 * variance)
+    }
+
+    // Sequential probability ratio test, alpha and beta are the error probabilities
+    pub fn sprt(&self, elo0: f64, elo1: f64, alpha: f64, beta: f64) -> SprtResult {
+        let llr = self.log_likelihood_ratio(elo0, elo1);
+        if llr >= ((1.0 - beta) / alpha).ln() {
+            SprtResult::AcceptH1
+        } else if llr <= (beta / (1.0 - alpha)).ln() {
+            SprtResult::AcceptH0
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+impl std::fmt::Display for MatchScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (elo, margin) = self.elo();
+        write!(
+            f,
+            "+{} ={} -{} elo {:.1} +/- {:.1}",
+            self.wins, self.draws, self.losses, elo, margin
+        )
+    }
+}
+
+// One opening per line, a FEN or moves from the start position like e2e4 e7e5
+pub fn load_openings(path: &str) -> Result<Vec<Game>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            parse_opening(line.trim())
+                .map_err(|error| format!("{}:{}: {}", path, line_number + 1, error))
+        })
+        .collect()
+}
+
+pub fn parse_opening(opening: &str) -> Result<Game, String> {
+    if opening.contains('/') {
+        return Ok(Game::from_fen(opening));
+    }
+    let mut game = Game::default();
+    for move_ in opening.split_whitespace() {
+        if move_.len() < 4 || !move_.is_ascii() {
+            return Err(format!("can't parse move {}", move_));
+        }
+        game.play(&absolute_move(&parse_move(move_), game.am_black()))?;
+    }
+    Ok(game)
+}
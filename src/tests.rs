@@ -1,8 +1,38 @@
 #![cfg(test)]
 
-use crate::board::{after_move, gen_moves, INITIAL_BOARD_STATE};
-use crate::search::{Searcher, MATE_LOWER};
-use crate::ui::{from_fen, parse_move, render_board, render_move};
+use crate::bench::{bench, BENCH_FENS};
+use crate::board::{
+    after_move, after_move_with, can_capture_king, gen_moves, is_in_check, legal_moves, move_value,
+    static_score, BoardState, A1, BOARD_SIZE, INITIAL_BOARD_STATE,
+};
+use crate::book::{decode_move, encode_move, polyglot_key, Book};
+use crate::epd::{load_epd, parse_epd};
+use crate::evaluation::{
+    king_attack, king_safety, king_shelter, move_delta, phase_after_move, piece_activity,
+    side_piece_activity, static_value, ClassicalEvaluator, Evaluator,
+};
+use crate::game::{Game, GameResult, INITIAL_FEN};
+use crate::parameters::{EvalParameters, DEFAULT_PARAMETERS};
+use crate::pawns::{pawn_bitboards, pawn_structure};
+use crate::pgn::parse_pgn;
+use crate::pieces::{Square, MAX_PHASE};
+use crate::search::{
+    mate_moves, SearchAlgorithm, SearchConfig, SearchLimits, Searcher, MATE_LOWER,
+};
+use crate::see::static_exchange;
+use crate::selfplay::{parse_opening, play_game, MatchScore, SearcherPlayer, SprtResult};
+use crate::trace::eval_trace;
+use crate::tuning::{
+    fit_scaling_constant, mean_squared_error, parse_labelled_position, quiescence_scores,
+    quiescence_scores_with, sigmoid, tunable_parameter, TUNABLE_PARAMETERS,
+};
+use crate::uci::go_limits;
+use crate::ui::{
+    absolute_move, from_fen, parse_move, parse_san, render_board, render_move, render_position,
+    render_san, try_from_fen,
+};
+use crate::xboard::{parse_level, time_for_move, xboard_loop_with_parameters};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 #[test]
@@ -121,7 +151,7 @@ fn sicilian() {
 
 #[test]
 fn moves() {
-    let move_fens = vec![
+    let move_fens = [
         "r1b1k2r/3n1p1p/p2PpnpR/qpp1p3/5P2/2N5/PPPQB1P1/1K1R2N1 w kq - 0 16",
         "7k/7p/8/1p5R/1P6/2Pb4/1r4PK/8 w - - 1 42",
         "8/5p1p/2p1p1pk/4Q3/7P/5qP1/r4P2/2R3K1 b - - 0 34",
@@ -187,42 +217,43 @@ fn moves() {
     }
 }
 
+const MATE_FENS: [&str; 10] = [
+    "1r1r1n1k/4qpnP/p1b1p1pQ/P2pP1N1/2pP2P1/1pP5/1P3PK1/RB5R w - - 7 31",
+    "r5qr/p1R1B1p1/Q3p3/4Pp2/4n1k1/1P2P1Pp/P4P1P/5RK1 w - - 3 22",
+    "r5qr/p1R1B3/4p1k1/4P1p1/4pR2/1P2P1Pp/P3Q2P/6K1 w - - 2 26",
+    "2b1r1k1/5p2/1pp4Q/4p3/6p1/r2B1P2/2P3PP/q1B1K1NR w K - 0 22",
+    "r1bq1b1r/ppp4p/2n3p1/4p3/3Pp3/4B1P1/PPP1QP1P/R3K2k w Q - 0 15",
+    "2kr1b1r/R3qp1p/bQ5p/2Pp4/3P4/5N2/5PPP/5K1R w - - 0 20",
+    "r2qkb1r/ppp2ppp/2n2n2/8/2BP1P2/1Q3b2/PP4PP/RNB1K2R w KQkq - 0 9",
+    "3N4/p6k/b3N1pp/3pp3/R4p2/7P/P1r3PB/6K1 b - - 1 34",
+    "2rR4/p4k2/1p2p2Q/5p2/5P2/8/PPP3q1/1KB4R b - - 0 27",
+    "3r1r1k/1p2Nppp/p4n2/P1p1p3/4P3/6Pq/2P1NP2/R1B1QRK1 b - - 2 18",
+];
+
+const MATE_SOLUTIONS: [&str; 10] = [
+    "h6g7", "a6e2", "f4f6", "d3h7", "e2f1", "b6a6", "c4f7", "f7f8", "b7f7", "c3b5",
+];
+
 #[test]
 fn mates() {
     // Since search exits early on mate found, can be used for benchmarking
-    let mate_fens = vec![
-        "1r1r1n1k/4qpnP/p1b1p1pQ/P2pP1N1/2pP2P1/1pP5/1P3PK1/RB5R w - - 7 31",
-        "r5qr/p1R1B1p1/Q3p3/4Pp2/4n1k1/1P2P1Pp/P4P1P/5RK1 w - - 3 22",
-        "r5qr/p1R1B3/4p1k1/4P1p1/4pR2/1P2P1Pp/P3Q2P/6K1 w - - 2 26",
-        "2b1r1k1/5p2/1pp4Q/4p3/6p1/r2B1P2/2P3PP/q1B1K1NR w K - 0 22",
-        "r1bq1b1r/ppp4p/2n3p1/4p3/3Pp3/4B1P1/PPP1QP1P/R3K2k w Q - 0 15",
-        "2kr1b1r/R3qp1p/bQ5p/2Pp4/3P4/5N2/5PPP/5K1R w - - 0 20",
-        "r2qkb1r/ppp2ppp/2n2n2/8/2BP1P2/1Q3b2/PP4PP/RNB1K2R w KQkq - 0 9",
-        "3N4/p6k/b3N1pp/3pp3/R4p2/7P/P1r3PB/6K1 b - - 1 34",
-        "2rR4/p4k2/1p2p2Q/5p2/5P2/8/PPP3q1/1KB4R b - - 0 27",
-        "3r1r1k/1p2Nppp/p4n2/P1p1p3/4P3/6Pq/2P1NP2/R1B1QRK1 b - - 2 18",
-    ];
-
-    let mate_solutions = vec![
-        "h6g7", "a6e2", "f4f6", "d3h7", "e2f1", "b6a6", "c4f7", "f7f8", "b7f7", "c3b5",
-    ];
-
     let time_for_mate = Duration::new(10, 0); // Max time to solve, should take much less N.B. compile as --release
 
     let mates_start_time = Instant::now();
-    for (puzzle, solution) in mate_fens.iter().zip(mate_solutions) {
+    for (puzzle, solution) in MATE_FENS.iter().zip(MATE_SOLUTIONS) {
         let mut searcher = Searcher::default();
         // println!("{}", render_board(&from_fen(puzzle)));
         let mate_start_time = Instant::now();
-        let (top_move, score, depth) = searcher.search(from_fen(puzzle), time_for_mate);
+        let (top_move, score, depth, stats) =
+            searcher.search(from_fen(puzzle), SearchLimits::time(time_for_mate));
         println!(
-            "Reached depth {} in {:?} nodes {} score {}",
+            "Reached depth {} in {:?} score {} {}",
             depth,
             mate_start_time.elapsed(),
-            searcher.nodes,
-            score
+            score,
+            stats
         );
-        assert_eq!(render_move(&top_move), solution);
+        assert_eq!(render_move(&top_move.unwrap()), solution);
         assert!(score > MATE_LOWER);
     }
     println!(
@@ -250,20 +281,1239 @@ fn puzzles() {
     let puzzle_solutions = vec![
         "g2g8", "b6d7", "f3e5", "e5g6", "e6b3", "e4e5", "b2b4", "f5g4", "e5c3", "h5g6", "g2g4",
     ];
-    let time_for_puzzle = Duration::from_millis(1600);
+    // Deep enough for all of them, much faster than searching for a fixed time
+    let depth_for_puzzle = 7;
     for (puzzle, solution) in puzzle_fens.iter().zip(puzzle_solutions) {
         let mut searcher = Searcher::default();
         let solve_start_time = Instant::now();
 
-        let (top_move, score, depth) = searcher.search(from_fen(puzzle), time_for_puzzle);
+        let (top_move, score, depth, stats) =
+            searcher.search(from_fen(puzzle), SearchLimits::depth(depth_for_puzzle));
         println!(
-            "Reached depth {} with score {} with nodes {} in {:?}",
+            "Reached depth {} with score {} in {:?} {}",
             depth,
             score,
-            searcher.nodes,
-            solve_start_time.elapsed()
+            solve_start_time.elapsed(),
+            stats
         );
         println!("puzzle {} solution {}", puzzle, solution);
-        assert_eq!(render_move(&top_move), solution);
+        assert_eq!(render_move(&top_move.unwrap()), solution);
+    }
+}
+
+#[test]
+fn search_statistics() {
+    let mut searcher = Searcher::default();
+    let (_top_move, _score, depth, stats) =
+        searcher.search(INITIAL_BOARD_STATE, SearchLimits::depth(5));
+    assert_eq!(depth, 5);
+    assert_eq!(stats, searcher.stats);
+    assert!(stats.nodes > stats.quiescence_nodes);
+    assert!(stats.quiescence_nodes > 0);
+    assert!(stats.beta_cutoffs >= stats.first_move_cutoffs);
+    assert!(stats.beta_cutoffs >= stats.killer_cutoffs);
+    assert!(stats.table_hits >= stats.table_cutoffs);
+    assert!(stats.selective_depth >= depth);
+    assert!(stats.effective_branching_factor() > 0.0);
+    assert!((0.0..=1.0).contains(&stats.first_move_cutoff_rate()));
+    // Quiet moves causing cutoffs are remembered for move ordering
+    assert!(searcher.history.iter().flatten().any(|&value| value > 0));
+    assert!(searcher.killers.iter().any(|killers| killers[0].is_some()));
+}
+
+#[test]
+fn search_limits() {
+    // Without a time limit the same search returns the same move and statistics
+    for limits in [SearchLimits::depth(5), SearchLimits::nodes(20_000)] {
+        let results: Vec<_> = (0..2)
+            .map(|_| Searcher::default().search(from_fen(MATE_FENS[6]), limits))
+            .collect();
+        assert_eq!(results[0], results[1]);
+    }
+
+    // Stops at the first node of depth 4, the one going over the limit
+    let mut searcher = Searcher::default();
+    let (top_move, _score, depth, stats) =
+        searcher.search(INITIAL_BOARD_STATE, SearchLimits::depth(3));
+    assert_eq!(depth, 3);
+    let mut searcher = Searcher::default();
+    let limited = searcher.search(INITIAL_BOARD_STATE, SearchLimits::nodes(stats.nodes));
+    assert_eq!((limited.0, limited.2), (top_move, 3));
+    assert_eq!(limited.3.nodes, stats.nodes + 1);
+
+    // A mate in 4, found by depth 7. Check extensions see it at depth 5, but they are off with
+    // a mate limit so it isn't found when searching for a mate in 3
+    let mut searcher = Searcher::default();
+    let (top_move, score, depth, _stats) =
+        searcher.search(from_fen(MATE_FENS[3]), SearchLimits::mate(4));
+    assert_eq!(render_move(&top_move.unwrap()), MATE_SOLUTIONS[3]);
+    assert!(score > MATE_LOWER);
+    assert!(depth <= 7);
+    let mut searcher = Searcher::default();
+    let (_top_move, score, depth, _stats) =
+        searcher.search(from_fen(MATE_FENS[3]), SearchLimits::mate(3));
+    assert!(score < MATE_LOWER);
+    assert_eq!(depth, 5);
+
+    // From UCI go commands, a missing or invalid value is ignored
+    let go = |command: &str| go_limits(&command.split(' ').collect::<Vec<_>>(), false);
+    assert_eq!(go("go depth 6"), SearchLimits::depth(6));
+    assert_eq!(go("go nodes 1000 mate 2").nodes, Some(1000));
+    assert_eq!(go("go nodes 1000 mate 2").mate, Some(2));
+    assert_eq!(
+        go("go movetime 100"),
+        SearchLimits::time(Duration::from_millis(100))
+    );
+    assert!(go("go depth").time.is_some());
+    assert!(go("go nodes x").time.is_some());
+    assert_eq!(go("go depth 5 nodes").depth, Some(5));
+
+    // No move when the game is over, checkmated or stalemated
+    for fen in [
+        "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3",
+        "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1",
+    ] {
+        let mut searcher = Searcher::default();
+        let (top_move, _score, _depth, _stats) =
+            searcher.search(from_fen(fen), SearchLimits::depth(3));
+        assert_eq!(top_move, None);
+    }
+}
+
+#[test]
+fn bench_signature() {
+    let first = bench(&DEFAULT_PARAMETERS, SearchConfig::default(), 3);
+    let second = bench(&DEFAULT_PARAMETERS, SearchConfig::default(), 3);
+    assert_eq!(
+        (first.nodes, first.signature),
+        (second.nodes, second.signature)
+    );
+    assert!(first.nodes >= BENCH_FENS.len() as u64);
+    // Turning off a search feature changes the node counts
+    let config = SearchConfig {
+        late_move_reductions: false,
+        ..SearchConfig::default()
+    };
+    assert_ne!(
+        bench(&DEFAULT_PARAMETERS, config, 3).signature,
+        first.signature
+    );
+}
+
+#[test]
+fn search_config() {
+    // All the optional features can be turned off, and still find the same mate
+    let plain = SearchConfig {
+        algorithm: SearchAlgorithm::MtdBi,
+        late_move_reductions: false,
+        futility_pruning: false,
+        reverse_futility_pruning: false,
+    };
+    let all = SearchConfig {
+        algorithm: SearchAlgorithm::MtdBi,
+        late_move_reductions: true,
+        futility_pruning: true,
+        reverse_futility_pruning: true,
+    };
+    for &config in [plain, SearchConfig::default(), all].iter() {
+        let mut searcher = Searcher::default();
+        searcher.config = config;
+        let (top_move, score, _depth, stats) = searcher.search(
+            from_fen(MATE_FENS[2]),
+            SearchLimits::time(Duration::new(10, 0)),
+        );
+        assert_eq!(render_move(&top_move.unwrap()), MATE_SOLUTIONS[2]);
+        assert!(score > MATE_LOWER);
+        assert_eq!(stats.late_move_reductions > 0, config.late_move_reductions);
+        assert!(stats.late_move_researches <= stats.late_move_reductions);
+        if config == plain {
+            assert_eq!(stats.futility_prunes, 0);
+            assert_eq!(stats.reverse_futility_cutoffs, 0);
+        }
+    }
+}
+
+#[test]
+fn principal_variation_search() {
+    // Same mates as the MTD-bi search, sharing the tables between the two algorithms
+    let mut searcher = Searcher::default();
+    for (index, (puzzle, solution)) in MATE_FENS.iter().zip(MATE_SOLUTIONS).enumerate() {
+        if index % 2 == 0 {
+            searcher.config.algorithm = SearchAlgorithm::Pvs;
+        } else {
+            searcher.config.algorithm = SearchAlgorithm::MtdBi;
+        }
+        let (top_move, score, _depth, _stats) =
+            searcher.search(from_fen(puzzle), SearchLimits::time(Duration::new(10, 0)));
+        assert_eq!(render_move(&top_move.unwrap()), solution);
+        assert!(score > MATE_LOWER);
+    }
+
+    // Taking the queen wins, far outside the first aspiration window
+    let mut searcher = Searcher::default();
+    searcher.config.algorithm = SearchAlgorithm::Pvs;
+    let (top_move, score, depth, _stats) = searcher.search(
+        from_fen("4k3/8/8/3q4/4P3/8/8/R3K3 w - - 0 1"),
+        SearchLimits::depth(6),
+    );
+    assert_eq!(render_move(&top_move.unwrap()), "e4d5");
+    assert!(score > 1000);
+    assert_eq!(depth, 6);
+}
+
+#[test]
+fn iterative_search() {
+    // Pausing every few nodes doesn't change the search
+    for (puzzle, depth) in [
+        (MATE_FENS[1], 4),
+        (MATE_FENS[6], 5),
+        (MATE_FENS[9], 5),
+        (
+            "r1bq1b1r/ppp4p/2n3p1/4p3/3Pp3/4B1P1/PPP1QPkP/R3K2R b KQ - 1 14",
+            4,
+        ),
+    ] {
+        let board_state = from_fen(puzzle);
+        let mut searcher = Searcher::default();
+        let mut paused = Searcher::default();
+        for gamma in [-MATE_LOWER, -200, 0, 1, 150, MATE_LOWER] {
+            let score = searcher.bound(&board_state, gamma, depth, 0);
+            let mut search = paused.start_bound(&board_state, gamma, depth);
+            while paused.resume_bound(&mut search, 50).is_none() {}
+            assert_eq!(search.result, Some(score));
+            assert_eq!(searcher.stats, paused.stats);
+            assert_eq!(searcher.history, paused.history);
+            assert_eq!(searcher.killers, paused.killers);
+        }
+    }
+
+    // The stack goes from the root to the position searched
+    let board_state = from_fen(MATE_FENS[9]);
+    let mut searcher = Searcher::default();
+    let score = searcher.bound(&board_state, 0, 3, 0);
+    let nodes = searcher.stats.nodes;
+    let mut searcher = Searcher::default();
+    let mut search = searcher.start_bound(&board_state, 0, 3);
+    let mut pauses = 0;
+    while searcher.resume_bound(&mut search, 50).is_none() {
+        pauses += 1;
+        assert!(!search.path().is_empty());
+        assert_eq!(search.stack[0].board_state, board_state);
+        assert_eq!(search.stack.len(), search.path().len() + 1);
+    }
+    assert_eq!(search.result, Some(score));
+    assert!(search.stack.is_empty());
+    assert_eq!(searcher.stats.nodes, nodes);
+    assert!(pauses >= nodes / 50 - 1);
+}
+
+#[test]
+fn check_detection() {
+    assert!(!is_in_check(&INITIAL_BOARD_STATE));
+    // Checks by a knight, a pawn and a bishop behind a pinned rook, from both sides
+    assert!(is_in_check(&from_fen("4k3/8/3N4/8/8/8/8/4K3 b - - 0 1")));
+    assert!(is_in_check(&from_fen("4k3/8/8/8/8/8/3p4/4K3 w - - 0 1")));
+    assert!(!is_in_check(&from_fen("4k3/8/8/8/8/8/4p3/4K3 w - - 0 1")));
+    assert!(!is_in_check(&from_fen("4k3/8/8/1b6/8/3R4/8/5K2 w - - 0 1")));
+    let pinned = from_fen("4k3/8/8/1b6/8/3R4/8/5K2 w - - 0 1");
+    assert!(can_capture_king(&after_move(&pinned, &parse_move("d3d8"))));
+    assert!(!can_capture_king(&after_move(&pinned, &parse_move("f1g2"))));
+
+    // From problematic_positions, taking the rook walks into a mate
+    let mut searcher = Searcher::default();
+    let (top_move, _score, _depth, _stats) = searcher.search(
+        from_fen("r1bq1b1r/ppp4p/2n3p1/4p3/3Pp3/4B1P1/PPP1QPkP/R3K2R b KQ - 1 14"),
+        SearchLimits::depth(7),
+    );
+    let (start, end) = top_move.unwrap();
+    assert_ne!(
+        render_move(&(BOARD_SIZE - 1 - start, BOARD_SIZE - 1 - end)),
+        "g2h1"
+    );
+
+    // The pinned knight doesn't mate, when in check taking the king is still searched
+    let pinned = from_fen("6rk/6pp/7b/6N1/8/8/8/2K5 w - - 0 1");
+    assert!(is_in_check(&after_move(&pinned, &parse_move("g5f7"))));
+    for algorithm in [SearchAlgorithm::MtdBi, SearchAlgorithm::Pvs] {
+        let mut searcher = Searcher::default();
+        searcher.config.algorithm = algorithm;
+        let (top_move, score, _depth, _stats) = searcher.search(pinned, SearchLimits::depth(3));
+        assert!(legal_moves(&pinned).contains(&top_move.unwrap()));
+        assert!(score < MATE_LOWER);
     }
+
+    // Mated however deep in QS, a side in check doesn't stand pat
+    let mut searcher = Searcher::default();
+    let mated = from_fen("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3");
+    for depth in [0, -3, -10] {
+        assert!(searcher.bound(&mated, -MATE_LOWER, depth, 1) < -MATE_LOWER);
+    }
+
+    // Both queens can keep checking, the extensions stop at twice the depth before QS
+    for depth in 1..=6 {
+        let mut searcher = Searcher::default();
+        let (_top_move, _score, _depth, stats) = searcher.search(
+            from_fen("7k/8/6Q1/8/8/1q6/8/K7 w - - 0 1"),
+            SearchLimits::depth(depth),
+        );
+        assert!(stats.selective_depth < 2 * depth + 8);
+    }
+
+    // The extensions find a mate in 5 at depth 5, the search goes on to find the mate in 3
+    let mut searcher = Searcher::default();
+    let (top_move, score, depth, _stats) =
+        searcher.search(from_fen(MATE_FENS[4]), SearchLimits::depth(9));
+    assert_eq!(render_move(&top_move.unwrap()), MATE_SOLUTIONS[4]);
+    assert_eq!(mate_moves(score), Some(3));
+    assert_eq!(depth, 6);
+}
+
+#[test]
+fn tapered_eval() {
+    assert_eq!(INITIAL_BOARD_STATE.phase, MAX_PHASE);
+
+    // Only kings and pawns, the score is the endgame score
+    let pawn_endgame = from_fen("8/5k2/8/8/3K4/8/4P3/8 w - - 0 1");
+    assert_eq!(pawn_endgame.phase, 0);
+    assert_eq!(pawn_endgame.score, pawn_endgame.endgame_score);
+
+    // In the endgame kings want to be in the center
+    let cornered_king = from_fen("8/5k2/8/8/8/8/4P3/K7 w - - 0 1");
+    assert!(pawn_endgame.score > cornered_king.score);
+
+    // Incremental updates agree with static evaluation on promotions and captures
+    let promotion = from_fen("1r5k/P7/8/8/8/8/8/K7 w - - 0 1");
+    for move_ in gen_moves(&promotion) {
+        let new_board_state = after_move(&promotion, &move_);
+        assert_eq!(
+            ClassicalEvaluator::default().static_scores(&new_board_state.board),
+            (
+                new_board_state.midgame_score,
+                new_board_state.endgame_score,
+                new_board_state.phase
+            )
+        );
+    }
+    let promoted = after_move(&promotion, &parse_move("a7b8"));
+    assert_eq!(promoted.phase, 4);
+
+    // The board functions use the default evaluator
+    let capture = parse_move("a7b8");
+    assert_eq!(
+        move_value(&promotion, &capture),
+        ClassicalEvaluator::default().move_value(&promotion, &capture)
+    );
+    assert_eq!(static_score(promoted.board), promoted.score);
+}
+
+// Only counts material, ignoring piece positions
+struct MaterialEvaluator;
+
+impl Evaluator for MaterialEvaluator {
+    fn scores_after_move(
+        &self,
+        board_state: &BoardState,
+        move_: &(usize, usize),
+    ) -> (i32, i32, i32) {
+        let delta = move_delta(board_state, move_, |piece, _| piece.midgame_value(A1));
+        (
+            board_state.midgame_score + delta,
+            board_state.endgame_score + delta,
+            phase_after_move(board_state, move_),
+        )
+    }
+
+    fn static_scores(&self, board: &[Square; BOARD_SIZE]) -> (i32, i32, i32) {
+        let score = static_value(board, |piece, _| piece.midgame_value(A1));
+        (score, score, 0)
+    }
+}
+
+#[test]
+fn custom_evaluator() {
+    // Scores are recomputed with the searcher's evaluator
+    let mut searcher = Searcher::with_evaluator(MaterialEvaluator);
+    let (top_move, score, _depth, _stats) = searcher.search(
+        from_fen(MATE_FENS[4]),
+        SearchLimits::time(Duration::new(10, 0)),
+    );
+    assert_eq!(render_move(&top_move.unwrap()), MATE_SOLUTIONS[4]);
+    assert!(score > MATE_LOWER);
+
+    let mut material_only = INITIAL_BOARD_STATE;
+    MaterialEvaluator.rescore(&mut material_only);
+    assert_eq!(material_only.score, 0);
+    let after_capture = after_move(
+        &from_fen("7k/8/8/3p4/4P3/8/8/K7 w - - 0 1"),
+        &parse_move("e4d5"),
+    );
+    assert_eq!(
+        MaterialEvaluator.static_score(&after_capture.board),
+        -Square::MyPawn.midgame_value(A1)
+    );
+}
+
+#[test]
+fn pawn_structure_terms() {
+    let structure = |fen| {
+        let (my_pawns, opponent_pawns) = pawn_bitboards(&from_fen(fen).board);
+        pawn_structure(my_pawns, opponent_pawns)
+    };
+
+    // Symmetric pawns
+    assert_eq!(
+        structure("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+        (0, 0)
+    );
+    // Isolated passed pawn on the second rank
+    assert_eq!(
+        structure("4k3/8/8/8/8/8/3P4/4K3 w - - 0 1"),
+        (-5 + 10, -15 + 28)
+    );
+    // Doubled isolated pawns, only the front one is passed
+    assert_eq!(
+        structure("4k3/8/8/8/8/3P4/3P4/4K3 w - - 0 1"),
+        (-11 - 2 * 5 + 17, -56 - 2 * 15 + 33)
+    );
+    // Backward d2 pawn against an isolated black pawn
+    assert_eq!(
+        structure("4k3/8/8/8/4p3/4P3/3P4/4K3 w - - 0 1"),
+        (-9 + 5, -24 + 15)
+    );
+    // Same position from black's point of view
+    assert_eq!(
+        structure("4k3/8/8/8/4p3/4P3/3P4/4K3 b - - 0 1"),
+        (9 - 5, 24 - 15)
+    );
+    // Advanced passed pawns against a blocked pawn
+    assert_eq!(
+        structure("4k3/1P6/8/8/8/8/6p1/4K3 b - - 0 1"),
+        (276 - 276 - 5 + 5, 260 - 260 - 15 + 15)
+    );
+
+    // Incremental updates with the pawn hash table agree with static evaluation
+    let evaluator = ClassicalEvaluator::default();
+    let board_state = from_fen("4k3/8/8/2pP4/8/4P3/3P4/4K3 w - c6 0 1");
+    for move_ in gen_moves(&board_state) {
+        let mut new_board_state = after_move(&board_state, &move_);
+        let incremental = new_board_state;
+        evaluator.rescore(&mut new_board_state);
+        assert_eq!(incremental, new_board_state);
+        assert_eq!(
+            after_move_with(&evaluator, &board_state, &move_),
+            incremental
+        );
+    }
+    assert!(!evaluator.pawn_hash_table.is_empty());
+
+    // The evaluator of after_move doesn't allocate a table for single moves
+    let uncached = ClassicalEvaluator::without_pawn_hash_table(&DEFAULT_PARAMETERS);
+    for move_ in gen_moves(&board_state) {
+        uncached.move_value(&board_state, &move_);
+    }
+    assert!(uncached.pawn_hash_table.is_empty());
+}
+
+#[test]
+fn king_safety_terms() {
+    assert_eq!(king_safety(&INITIAL_BOARD_STATE), (0, 0));
+
+    // Full pawn shield in front of a castled king
+    assert_eq!(
+        king_shelter(&from_fen("6k1/5ppp/8/8/8/8/5PPP/6K1 w - - 0 1").board),
+        72
+    );
+    // Semi-open file in front of the king
+    assert_eq!(
+        king_shelter(&from_fen("6k1/5ppp/8/8/8/8/5P1P/6K1 w - - 0 1").board),
+        48 - 20
+    );
+    // Open file in front of the king
+    assert_eq!(
+        king_shelter(&from_fen("6k1/5p1p/8/8/8/8/5P1P/6K1 w - - 0 1").board),
+        48 - 20 - 15
+    );
+
+    // Queen and rook next to the king
+    let attack = from_fen("6k1/5p1p/6QR/8/8/8/8/6K1 w - - 0 1");
+    assert!(king_attack(&attack) > 0);
+    // A single attacker is not enough
+    let attack = from_fen("6k1/5p1p/6Q1/8/8/8/8/6K1 w - - 0 1");
+    assert_eq!(king_attack(&attack), 0);
+
+    // The side to move in mate positions usually has the safer king
+    let safer_king = MATE_FENS
+        .iter()
+        .filter(|fen| king_safety(&from_fen(fen)).0 > 0)
+        .count();
+    assert!(safer_king >= 8);
+}
+
+#[test]
+fn piece_activity_terms() {
+    assert_eq!(piece_activity(&INITIAL_BOARD_STATE), (0, 0));
+
+    let activity = |fen| side_piece_activity(&from_fen(fen));
+    let difference = |(a, b): (i32, i32), (c, d): (i32, i32)| (a - c, b - d);
+
+    // Knight on e5 supported by a pawn on d4, without changing its mobility
+    assert_eq!(
+        difference(
+            activity("4k3/8/8/4N3/3P4/8/8/4K3 w - - 0 1"),
+            activity("4k3/8/8/4N3/P7/8/8/4K3 w - - 0 1")
+        ),
+        (30, 21)
+    );
+    // An opponent pawn could chase it away
+    assert_eq!(
+        activity("4k3/8/5p2/4N3/3P4/8/8/4K3 w - - 0 1"),
+        activity("4k3/8/p7/4N3/P7/8/8/4K3 w - - 0 1")
+    );
+
+    // The two bishops do not block each other
+    assert_eq!(
+        difference(
+            difference(
+                activity("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1"),
+                activity("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1")
+            ),
+            activity("4k3/8/8/8/8/8/8/4KB2 w - - 0 1")
+        ),
+        (45, 60)
+    );
+
+    // Open file, semi-open file, own pawn in front
+    let open = activity("4k3/7p/8/8/8/8/7P/R3K3 w - - 0 1");
+    let semi_open = activity("4k3/p7/8/8/8/8/7P/R3K3 w - - 0 1");
+    let closed = activity("4k3/7p/8/8/8/8/P7/R3K3 w - - 0 1");
+    assert!(open.0 > semi_open.0 && semi_open.0 > closed.0);
+
+    // A trapped bishop is worse than a centralised one
+    assert!(
+        activity("4k3/8/8/8/8/1P6/P7/B3K3 w - - 0 1").0
+            < activity("4k3/8/8/8/3B4/1P6/P7/4K3 w - - 0 1").0
+    );
+}
+
+#[test]
+fn evaluation_trace() {
+    let trace = eval_trace(&INITIAL_BOARD_STATE);
+    assert!(trace
+        .terms
+        .iter()
+        .all(|(_, mine, opponent)| mine == opponent));
+    assert_eq!(trace.total(), (0, 0));
+    assert_eq!(trace.phase, MAX_PHASE);
+
+    // The terms add up to the full evaluation
+    let evaluator = ClassicalEvaluator::default();
+    for fen in MATE_FENS.iter().chain(BENCH_FENS.iter()) {
+        let board_state = from_fen(fen);
+        let trace = eval_trace(&board_state);
+        assert_eq!(trace.score(), evaluator.evaluate(&board_state));
+        assert!(trace
+            .render_table(("White", "Black"))
+            .contains("Pawn structure"));
+    }
+}
+
+#[test]
+fn evaluation_parameters() {
+    // The built-in tables survive writing and reading them back
+    let text = DEFAULT_PARAMETERS.to_string();
+    assert_eq!(EvalParameters::parse(&text), Ok(DEFAULT_PARAMETERS.clone()));
+    assert_eq!(Square::MyKnight.midgame_value(A1), 782 - 169);
+    assert_eq!(Square::MyKing.endgame_value(A1), 32000 + 1);
+
+    let invalid = [
+        (text.replace("queen = 2529", ""), "missing midgame.queen"),
+        (
+            text.replace("queen = 2529", "queen = 2529 1"),
+            "midgame.queen has 2 values",
+        ),
+        (
+            text.replace("king = 32000", "king = 100"),
+            "midgame king value must be 32000",
+        ),
+        (
+            text.replace("rook = 1289", "rook = 0"),
+            "midgame rook value 0 is not between",
+        ),
+        (
+            text.replace("rook = 1289", "rook = 1289\nrooks = 1"),
+            "unknown key midgame.rooks",
+        ),
+        (
+            text.replace("rook = 1289", "rook = a lot"),
+            "invalid number in rook",
+        ),
+        (
+            text.replace("[endgame]", "[endgames]"),
+            "unknown section endgames",
+        ),
+        (
+            text.replace("   -200,", ""),
+            "midgame.knight_table has 63 values",
+        ),
+    ];
+    for (text, error) in invalid.iter() {
+        let result = EvalParameters::parse(text);
+        assert!(
+            result.as_ref().unwrap_err().contains(error),
+            "{:?} should contain {}",
+            result,
+            error
+        );
+    }
+
+    // Evaluation follows the loaded parameters
+    let mut parameters = DEFAULT_PARAMETERS.clone();
+    parameters.midgame_piece_values[0] *= 2;
+    parameters.endgame_piece_values[0] *= 2;
+    let extra_pawn = from_fen("4k3/8/8/8/8/8/3P4/4K3 w - - 0 1");
+    let default_score = ClassicalEvaluator::default().static_score(&extra_pawn.board);
+    let score = ClassicalEvaluator::with_parameters(&parameters).static_score(&extra_pawn.board);
+    assert!(score > default_score + 100);
+}
+
+#[test]
+fn texel_tuning() {
+    let (_, result) =
+        parse_labelled_position("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 [0.5]")
+            .unwrap();
+    assert_eq!(result, 0.5);
+    // Results are converted to the point of view of the side to move
+    let (_, result) = parse_labelled_position("4k3/8/8/8/8/8/3PPP2/4K3 b - - c9 \"1-0\";").unwrap();
+    assert_eq!(result, 0.0);
+    assert!(parse_labelled_position("4k3/8/8/8/8/8/3PPP2/4K3 w - - 0 1 white").is_err());
+    assert!(parse_labelled_position("4k3/8/8/8/8/8/3PPP2/4K3 1-0").is_err());
+
+    // The queen hanging to a pawn is taken in the quiescence search
+    let positions = [
+        parse_labelled_position("4k3/8/8/3q4/4P3/8/8/4K3 w - - 0 1 1-0").unwrap(),
+        parse_labelled_position("4k3/8/8/8/8/8/3PPP2/4K3 w - - 0 1 1-0").unwrap(),
+        parse_labelled_position("4k3/pppppppp/8/8/8/8/8/4K3 w - - 0 1 0-1").unwrap(),
+        parse_labelled_position("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1 1/2-1/2").unwrap(),
+    ];
+    let scores = quiescence_scores(&DEFAULT_PARAMETERS, &positions);
+    assert!(scores[0] > 0);
+    assert!(scores[0] > ClassicalEvaluator::default().evaluate(&positions[0].0));
+    assert!(scores[1] > 0 && scores[2] < 0);
+
+    assert_eq!(sigmoid(0, 1.0), 0.5);
+    assert!(sigmoid(200, 1.0) > 0.5 && sigmoid(-200, 1.0) < 0.5);
+    let scaling_constant = fit_scaling_constant(&scores, &positions);
+    assert!(scaling_constant > 0.0);
+    assert!(
+        mean_squared_error(&scores, &positions, scaling_constant)
+            <= mean_squared_error(&scores, &positions, 1.0)
+    );
+
+    // All piece values and table entries except the pawns on the first and last rank
+    let mut parameters = DEFAULT_PARAMETERS.clone();
+    let tunable = (0..TUNABLE_PARAMETERS)
+        .filter(|&index| tunable_parameter(&mut parameters, index).is_some())
+        .count();
+    assert_eq!(tunable, 10 + 12 * 64 - 2 * 16);
+
+    // Tables reused with other parameters give the same scores as new ones
+    parameters.midgame_piece_values[4] -= 1000;
+    let mut searcher = Searcher::with_evaluator(ClassicalEvaluator::default());
+    assert_eq!(quiescence_scores_with(&mut searcher, &positions), scores);
+    let mut searcher =
+        searcher.with_new_evaluator(ClassicalEvaluator::with_parameters(&parameters));
+    assert_eq!(
+        quiescence_scores_with(&mut searcher, &positions),
+        quiescence_scores(&parameters, &positions)
+    );
+}
+
+#[test]
+fn static_exchange_evaluation() {
+    let exchange = |fen: &str, move_| {
+        let (start, end) = parse_move(move_);
+        // The board is rotated when black is to move
+        if fen.contains(" b ") {
+            let rotated = (BOARD_SIZE - 1 - start, BOARD_SIZE - 1 - end);
+            static_exchange(&from_fen(fen), &rotated)
+        } else {
+            static_exchange(&from_fen(fen), &(start, end))
+        }
+    };
+    let (pawn, knight, rook, queen) = (136, 782, 1289, 2529);
+
+    // Undefended knight
+    assert_eq!(
+        exchange("4k3/8/8/3n4/4P3/8/8/4K3 w - - 0 1", "e4d5"),
+        knight
+    );
+    // Queen taking a pawn defended by a pawn
+    assert_eq!(
+        exchange("4k3/8/2p5/3p4/8/8/8/3QK3 w - - 0 1", "d1d5"),
+        pawn - queen
+    );
+    // Knight taking a pawn defended by the queen
+    assert_eq!(
+        exchange("3qk3/8/8/3p4/8/4N3/8/4K3 w - - 0 1", "e3d5"),
+        pawn - knight
+    );
+    // Rook taking a pawn defended by a rook, with and without a second rook behind it
+    assert_eq!(
+        exchange("3rk3/8/8/3p4/8/8/3R4/4K3 w - - 0 1", "d2d5"),
+        pawn - rook
+    );
+    assert_eq!(
+        exchange("3rk3/8/8/3p4/8/8/3R4/3RK3 w - - 0 1", "d2d5"),
+        pawn
+    );
+    // The king can't recapture on a defended square
+    assert_eq!(
+        exchange("4k3/4p3/8/8/8/8/4R3/6K1 w - - 0 1", "e2e7"),
+        pawn - rook
+    );
+    assert_eq!(
+        exchange("4k3/4p3/8/8/8/8/4R3/4R1K1 w - - 0 1", "e2e7"),
+        pawn
+    );
+    // En passant and capturing promotion
+    assert_eq!(exchange("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), pawn);
+    assert_eq!(
+        exchange("2r1k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7c8"),
+        rook + queen - pawn
+    );
+    // Same exchanges with black to move
+    assert_eq!(
+        exchange("3qk3/8/8/8/3P4/2P5/8/4K3 b - - 0 1", "d8d4"),
+        pawn - queen
+    );
+    assert_eq!(
+        exchange("4k3/8/8/3p4/4N3/8/8/4K3 b - - 0 1", "d5e4"),
+        knight
+    );
+}
+
+#[test]
+fn epd_positions() {
+    let san = |fen: &str, san: &str| {
+        let board_state = from_fen(fen);
+        parse_san(&board_state, fen.contains(" b "), san)
+            .map(|move_| render_move(&absolute_move(&move_, fen.contains(" b "))))
+    };
+    let castling = "r3k2r/8/8/8/8/8/5r2/R3K2R w KQkq - 0 1";
+    assert_eq!(san(castling, "O-O-O").as_deref(), Some("e1c1"));
+    // Through the check of the rook on f2
+    assert_eq!(san(castling, "O-O"), None);
+    assert_eq!(
+        san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "O-O").as_deref(),
+        Some("e8g8")
+    );
+    let knights = "4k3/8/8/8/8/8/8/1N1K1N2 w - - 0 1";
+    assert_eq!(san(knights, "Nd2"), None);
+    assert_eq!(san(knights, "Nfd2").as_deref(), Some("f1d2"));
+    assert_eq!(san(knights, "N1xd2+"), None);
+    assert_eq!(
+        san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8=Q+").as_deref(),
+        Some("b7b8")
+    );
+    assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8=N"), None);
+    // The bishop is pinned
+    assert_eq!(san("4k3/4r3/8/8/8/8/4B3/4K3 w - - 0 1", "Bd3"), None);
+    assert_eq!(
+        san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "exd6").as_deref(),
+        Some("e5d6")
+    );
+
+    let position = parse_epd(
+        "8/7p/5k2/5p2/p1p2P2/Pr1pPK2/1P1R3P/8 b - - bm Rxb2; id \"WAC.002\"; c0 \"a;b\";",
+    )
+    .unwrap();
+    assert_eq!(position.id, "WAC.002");
+    assert!(position.am_black);
+    let rook_takes = absolute_move(&parse_move("b3b2"), true);
+    assert_eq!(position.best_moves, vec![rook_takes]);
+    assert!(position.is_solved_by(&rook_takes));
+    assert!(!position.is_solved_by(&absolute_move(&parse_move("h7h5"), true)));
+    let position = parse_epd(
+        "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - am Ng5; id \"avoid\";",
+    )
+    .unwrap();
+    assert!(!position.is_solved_by(&parse_move("f3g5")));
+    assert!(position.is_solved_by(&parse_move("b1c3")));
+    assert!(parse_epd("4k3/8/8/8/8/8/8/4K3 w - - bm Qd1;").is_err());
+    let position = parse_epd("4k3/8/8/8/8/8/8/4K3 w - - acd 7; acs 2; c0 \"a note\";").unwrap();
+    assert_eq!(position.comment, "a note");
+    assert_eq!(position.limits.depth, Some(7));
+    assert_eq!(position.limits.time, Some(Duration::from_secs(2)));
+    assert!(parse_epd("4k3/8/8/8/8/8/8/4K3 w - - acd deep;").is_err());
+}
+
+// Ids of the positions in problematic_positions.epd that are still played badly
+const UNSOLVED_PROBLEMATIC_POSITIONS: [&str; 1] = ["simple.tactic"];
+
+#[test]
+fn problematic_positions() {
+    let positions = load_epd("problematic_positions.epd").unwrap();
+    for position in positions {
+        assert_ne!(position.limits, SearchLimits::default(), "{}", position.id);
+        let mut searcher = Searcher::default();
+        let (top_move, _score, _depth, _stats) =
+            searcher.search(position.board_state, position.limits);
+        let solved = position.is_solved_by(&top_move.unwrap());
+        if UNSOLVED_PROBLEMATIC_POSITIONS.contains(&position.id.as_str()) {
+            assert!(
+                !solved,
+                "{} is fixed, remove it from UNSOLVED_PROBLEMATIC_POSITIONS",
+                position.id
+            );
+        } else {
+            assert!(
+                solved,
+                "{} is played badly again: {}",
+                position.id, position.comment
+            );
+        }
+    }
+}
+
+#[test]
+fn self_play() {
+    let play = |game: &mut Game, moves: &str| {
+        for move_ in moves.split_whitespace() {
+            game.play(&absolute_move(&parse_move(move_), game.am_black()))
+                .unwrap();
+        }
+    };
+    let mut game = Game::default();
+    play(&mut game, "f2f3 e7e5 g2g4");
+    assert_eq!(game.result(), None);
+    assert!(game.play(&parse_move("e1e3")).is_err());
+    play(&mut game, "d8h4");
+    assert_eq!(game.result(), Some((GameResult::BlackWins, "checkmate")));
+    assert_eq!(game.uci_moves().join(" "), "f2f3 e7e5 g2g4 d8h4");
+    assert_eq!(game.undo(), Some(absolute_move(&parse_move("d8h4"), true)));
+
+    let mut game = Game::default();
+    play(&mut game, "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1");
+    assert_eq!(game.result(), None);
+    play(&mut game, "f6g8");
+    assert_eq!(
+        game.result(),
+        Some((GameResult::Draw, "threefold repetition"))
+    );
+    let game = Game::from_fen("4k3/8/8/8/8/8/8/3NK3 b - - 0 1");
+    assert_eq!(
+        game.result(),
+        Some((GameResult::Draw, "insufficient material"))
+    );
+
+    // Every move gets mated, search still has to return the only legal one
+    let board_state = from_fen("Q7/8/8/Q7/1k6/8/2P5/2K5 b - - 0 1");
+    for algorithm in [SearchAlgorithm::MtdBi, SearchAlgorithm::Pvs] {
+        let mut searcher = Searcher::default();
+        searcher.config.algorithm = algorithm;
+        let (top_move, score, _, _) = searcher.search(board_state, SearchLimits::depth(3));
+        assert_eq!(
+            render_move(&absolute_move(&top_move.unwrap(), true)),
+            "b4c4"
+        );
+        assert!(score <= -MATE_LOWER);
+        // Black is in check from the king after Ke6, but taking it back wins anyway
+        let board_state = from_fen("8/3k1KP1/8/8/8/8/p3R3/8 w - - 0 1");
+        for depth in 1..6 {
+            let (top_move, _, _, _) = searcher.search(board_state, SearchLimits::depth(depth));
+            assert!(legal_moves(&board_state).contains(&top_move.unwrap()));
+        }
+    }
+
+    let mut white = SearcherPlayer {
+        name: "white".to_string(),
+        parameters: Arc::new(DEFAULT_PARAMETERS.clone()),
+        config: SearchConfig::default(),
+    };
+    let mut black = SearcherPlayer {
+        name: "black".to_string(),
+        parameters: Arc::new(DEFAULT_PARAMETERS.clone()),
+        config: SearchConfig::default(),
+    };
+    let opening = parse_opening(MATE_FENS[0]).unwrap();
+    let (game, result, reason) =
+        play_game(&mut white, &mut black, opening, SearchLimits::depth(4), 20);
+    assert_eq!(
+        (result, reason.as_str()),
+        (GameResult::WhiteWins, "checkmate")
+    );
+    assert!(game.moves.len() <= 3);
+    assert!(parse_opening("e2e4 e7e5 e1e3").is_err());
+
+    let even = MatchScore {
+        wins: 10,
+        draws: 20,
+        losses: 10,
+    };
+    assert_eq!(even.elo().0, 0.0);
+    assert_eq!(even.sprt(-10.0, 10.0, 0.05, 0.05), SprtResult::Continue);
+    let mut winning = MatchScore::default();
+    for _ in 0..100 {
+        winning.add(GameResult::WhiteWins, true);
+        winning.add(GameResult::Draw, false);
+    }
+    assert_eq!(winning.games(), 200);
+    assert!(winning.elo().0 > 150.0);
+    assert_eq!(winning.sprt(0.0, 10.0, 0.05, 0.05), SprtResult::AcceptH1);
+}
+
+#[test]
+fn san_notation() {
+    let san = |fen: &str, san: &str| {
+        let board_state = from_fen(fen);
+        parse_san(&board_state, fen.contains(" b "), san)
+            .map(|move_| render_move(&absolute_move(&move_, fen.contains(" b "))))
+    };
+    let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    for notation in ["Nf3", "nf3", "Ng1-f3", "Ng1f3", "g1f3", "♘f3", "Nf3!?"] {
+        assert_eq!(
+            san(start, notation).as_deref(),
+            Some("g1f3"),
+            "{}",
+            notation
+        );
+    }
+    assert_eq!(san(start, "e2-e4").as_deref(), Some("e2e4"));
+    assert_eq!(san(start, "Pe4").as_deref(), Some("e2e4"));
+    assert_eq!(san(start, "e5"), None);
+    let castling = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1";
+    for notation in ["O-O", "0-0", "o-o", "e8g8", "Ke8g8"] {
+        assert_eq!(
+            san(castling, notation).as_deref(),
+            Some("e8g8"),
+            "{}",
+            notation
+        );
+    }
+    assert_eq!(san(castling, "Kg8"), None);
+    let promotion = "1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+    for notation in ["a8=Q", "a8Q", "a8(Q)", "a8/Q", "a7a8q"] {
+        assert_eq!(
+            san(promotion, notation).as_deref(),
+            Some("a7a8"),
+            "{}",
+            notation
+        );
+    }
+    assert_eq!(san(promotion, "axb8=Q+").as_deref(), Some("a7b8"));
+    assert_eq!(san(promotion, "ab8").as_deref(), Some("a7b8"));
+    assert_eq!(san(promotion, "a8=R"), None);
+    let en_passant = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+    assert_eq!(san(en_passant, "exd6 e.p.").as_deref(), Some("e5d6"));
+    assert_eq!(san(en_passant, "e5:d6").as_deref(), Some("e5d6"));
+    // A bishop and a pawn of the b file can both take on c3, b is the file unless no pawn can
+    let bishop_or_pawn = "4k3/8/8/8/8/2n5/1P1B4/4K3 w - - 0 1";
+    assert_eq!(san(bishop_or_pawn, "Bc3").as_deref(), Some("d2c3"));
+    assert_eq!(san(bishop_or_pawn, "bxc3").as_deref(), Some("b2c3"));
+    assert_eq!(san(bishop_or_pawn, "bc3").as_deref(), Some("b2c3"));
+    assert_eq!(san(bishop_or_pawn, "be3").as_deref(), Some("d2e3"));
+
+    // Every rendered move is parsed back
+    let fens = [start, castling, promotion, en_passant, bishop_or_pawn];
+    for fen in fens.iter().chain(MATE_FENS.iter()) {
+        let am_black = fen.contains(" b ");
+        let board_state = from_fen(fen);
+        for move_ in legal_moves(&board_state) {
+            let rendered = render_san(&board_state, am_black, &move_);
+            assert_eq!(
+                parse_san(&board_state, am_black, &rendered),
+                Some(move_),
+                "{} in {}",
+                rendered,
+                fen
+            );
+        }
+    }
+
+    let mut game = Game::default();
+    for notation in ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "O-O"] {
+        let move_ = parse_san(&game.board_state(), game.am_black(), notation).unwrap();
+        game.play(&move_).unwrap();
+    }
+    assert_eq!(game.san_moves().join(" "), "e4 e5 Nf3 Nc6 Bb5 a6 O-O");
+}
+
+#[test]
+fn pgn_games() {
+    let san = |fen: &str, move_: &str| {
+        let am_black = fen.contains(" b ");
+        let board_state = from_fen(fen);
+        render_san(
+            &board_state,
+            am_black,
+            &absolute_move(&parse_move(move_), am_black),
+        )
+    };
+    let knights = "4k3/8/8/8/8/8/8/1N1K1N2 w - - 0 1";
+    assert_eq!(san(knights, "b1d2"), "Nbd2");
+    assert_eq!(san("4k3/8/8/8/8/1N6/8/1N1K4 w - - 0 1", "b1d2"), "N1d2");
+    assert_eq!(san("2k5/8/8/8/1Q5Q/8/K7/7Q w - - 0 1", "h4e1"), "Qh4e1");
+    assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), "exd6");
+    assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8"), "O-O-O");
+    assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8"), "b8=Q+");
+    assert_eq!(
+        san(
+            "rnbqkbnr/pppp1ppp/8/4p3/6P1/5P2/PPPPP2P/RNBQKBNR b KQkq - 0 2",
+            "d8h4"
+        ),
+        "Qh4#"
+    );
+
+    let text = r#"[Event "Test \"quoted\""]
+[White "White"]
+[SetUp "1"]
+[FEN "4k3/1P6/8/8/8/8/8/4K2R b K - 0 40"]
+
+40... Kd7 $1 (40... Kf7 41.b8=Q (41. Rh7+ {check} Kg6) Kg6) 41. b8=Q!? ; rest of line
+Kc6 42. O-O 1-0
+
+1. e4 e5 2. Nf3 *"#;
+    let games = parse_pgn(text).unwrap();
+    assert_eq!(games.len(), 2);
+    let game = &games[0];
+    assert_eq!(game.tag("Event"), Some("Test \"quoted\""));
+    assert_eq!(game.result, Some(GameResult::WhiteWins));
+    assert_eq!(game.game.uci_moves().join(" "), "e8d7 b7b8q d7c6 e1g1");
+    assert_eq!(game.nags, vec![(1, 1), (2, 5)]);
+    assert_eq!(game.comments, vec![(2, "rest of line".to_string())]);
+    assert_eq!(game.variations.len(), 1);
+    let (index, variation) = &game.variations[0];
+    assert_eq!(*index, 0);
+    assert_eq!(variation.game.uci_moves().join(" "), "e8f7 b7b8q f7g6");
+    assert_eq!(
+        variation.variations[0].1.comments,
+        vec![(2, "check".to_string())]
+    );
+    assert_eq!(games[1].result, None);
+    assert_eq!(games[1].game.moves.len(), 3);
+
+    let written = game.to_string();
+    assert!(written.contains("[Result \"1-0\"]"));
+    assert!(written.replace('\n', " ").contains(
+        "40... Kd7 $1 (40... Kf7 41. b8=Q (41. Rh7+ {check} 41... Kg6) 41... Kg6) 41. b8=Q $5"
+    ));
+    let reparsed = parse_pgn(&written).unwrap();
+    assert_eq!(reparsed[0].to_string(), written);
+
+    assert!(parse_pgn("1. e4 e5 2. Ke3").is_err());
+    assert!(parse_pgn("1. e4 e5 2. Nf3)").is_err());
+    assert!(parse_pgn("1. e4 (1. d4 d5").is_err());
+    assert!(parse_pgn("[FEN \"4k3/1P6/8/8/8/8/8/4K3 w - - 0 1\"] 1. b8=N").is_err());
+}
+
+#[test]
+fn position_rendering() {
+    // Real colours, white's view for white and black's view for black
+    let fen = "4k3/8/8/8/8/8/8/R3K3 b - - 0 1";
+    let white = render_position(&from_fen(INITIAL_FEN), false);
+    assert!(white.starts_with(" 8  ♜ ♞ ♝ ♛ ♚ ♝ ♞ ♜\n"));
+    assert!(white.ends_with(" 1  ♖ ♘ ♗ ♕ ♔ ♗ ♘ ♖\n    a b c d e f g h\n"));
+    let black = render_position(&from_fen(fen), true);
+    assert!(black.starts_with(" 1  · · · ♔ · · · ♖\n"));
+    assert!(black.ends_with(" 8  · · · ♚ · · · ·\n    h g f e d c b a\n"));
+}
+
+#[test]
+fn xboard_time_control() {
+    let level = |command: &str| parse_level(&command.split(' ').collect::<Vec<_>>());
+    assert_eq!(
+        level("level 40 5 0"),
+        Some((40, Duration::from_secs(300), Duration::ZERO))
+    );
+    assert_eq!(
+        level("level 0 2:30 1.5"),
+        Some((0, Duration::from_secs(150), Duration::from_millis(1500)))
+    );
+    assert_eq!(level("level 40 five 0"), None);
+    assert_eq!(level("level 40"), None);
+    assert_eq!(level("level 40 5 -1"), None);
+    assert_eq!(level("level 40 5 NaN"), None);
+    assert_eq!(level("level 40 5 inf"), None);
+
+    // Never more than half of the clock, never less than a minimum
+    let time = time_for_move(Duration::from_secs(60), 30, Duration::ZERO);
+    assert!(Duration::from_millis(1800) < time && time < Duration::from_secs(2));
+    let with_increment = time_for_move(Duration::from_secs(60), 30, Duration::from_secs(2));
+    assert!(with_increment > time + Duration::from_secs(1));
+    let last_move = time_for_move(Duration::from_secs(10), 1, Duration::ZERO);
+    assert!(last_move <= Duration::from_secs(5));
+    let flagging = time_for_move(Duration::from_millis(10), 30, Duration::ZERO);
+    assert!(flagging > Duration::ZERO);
+}
+
+#[test]
+fn xboard_protocol() {
+    let xboard = |commands: &str| {
+        let mut output = Vec::new();
+        xboard_loop_with_parameters(
+            &DEFAULT_PARAMETERS,
+            SearchConfig::default(),
+            &mut commands.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+    let moves = |output: &str| {
+        output
+            .lines()
+            .filter_map(|line| line.strip_prefix("move "))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let game_after = |sans: &[&str]| {
+        let mut game = Game::default();
+        for san in sans {
+            let move_ = parse_san(&game.board_state(), game.am_black(), san).unwrap();
+            game.play(&move_).unwrap();
+        }
+        game
+    };
+
+    // The engine plays black and answers the moves of the user
+    let output = xboard("xboard\nnew\nsd 2\nusermove e2e4\nquit\n");
+    let played = moves(&output);
+    assert_eq!(played.len(), 1, "{}", output);
+    let game = game_after(&["e4"]);
+    assert!(parse_san(&game.board_state(), true, &played[0]).is_some());
+
+    // No answer in force mode, go plays the side to move
+    let output = xboard("new\nforce\nsd 2\nusermove e2e4\nusermove e7e5\n");
+    assert!(moves(&output).is_empty(), "{}", output);
+    let output = xboard("new\nforce\nsd 2\nusermove e2e4\nusermove e7e5\ngo\n");
+    let played = moves(&output);
+    assert_eq!(played.len(), 1, "{}", output);
+    let game = game_after(&["e4", "e5"]);
+    assert!(parse_san(&game.board_state(), false, &played[0]).is_some());
+
+    // After undo it is white to move again
+    let output = xboard("new\nforce\nusermove e2e4\nusermove d2d4\n");
+    assert!(output.contains("Illegal move: d2d4"), "{}", output);
+    let output = xboard("new\nforce\nusermove e2e4\nundo\nusermove d2d4\nusermove d7d5\n");
+    assert!(!output.contains("Illegal move"), "{}", output);
+
+    // Invalid positions are rejected and the game goes on from the previous one
+    assert!(try_from_fen(INITIAL_FEN).is_ok());
+    for fen in [
+        "garbage",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -",
+        "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1BNR w kq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - x 1",
+        "4k3/8/8/8/8/8/8/4R1K1 w - - 0 1",
+    ] {
+        assert!(try_from_fen(fen).is_err(), "{}", fen);
+    }
+    let output = xboard("new\nforce\nusermove e2e4\nsetboard garbage\nusermove e7e5\n");
+    assert!(
+        output.contains("tellusererror Illegal position"),
+        "{}",
+        output
+    );
+    assert!(!output.contains("Illegal move"), "{}", output);
+    let output = xboard("new\nforce\nsetboard 4k3/8/8/8/8/8/8/4K2R b K - 0 1\nusermove e8d7\n");
+    assert_eq!(output, "");
+
+    // sd limits the depth of the posted search
+    let output = xboard("new\npost\nsd 3\nusermove e2e4\n");
+    let post = output.lines().next().unwrap();
+    assert_eq!(post.split(' ').next(), Some("3"), "{}", output);
+
+    // st limits the time, invalid values are rejected and don't change it
+    let start = Instant::now();
+    let output = xboard("new\nst 0.2\nst -1\nst NaN\nst inf\nusermove e2e4\n");
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert_eq!(moves(&output).len(), 1, "{}", output);
+    assert_eq!(
+        output.matches("Error (invalid st)").count(),
+        3,
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn polyglot_book() {
+    // Keys from the Polyglot book format description, after each of the moves
+    let keys = [
+        ("", 0x463b96181691fc9c),
+        ("e4", 0x823c9b50fd114196),
+        ("e4 d5", 0x0756b94461c50fb0),
+        ("e4 d5 e5", 0x662fafb965db29d4),
+        ("e4 d5 e5 f5", 0x22a48b5a8e47ff78),
+        ("e4 d5 e5 f5 Ke2", 0x652a607ca3f242c1),
+        ("e4 d5 e5 f5 Ke2 Kf7", 0x00fdd303c946bdd9),
+        ("a4 b5 h4 b4 c4", 0x3c8123ea7b067637),
+        ("a4 b5 h4 b4 c4 bxc3 Ra3", 0x5c3f9b829b279560),
+    ];
+    for (moves, key) in keys.iter() {
+        let mut game = Game::default();
+        for san in moves.split_whitespace() {
+            let move_ = parse_san(&game.board_state(), game.am_black(), san).unwrap();
+            game.play(&move_).unwrap();
+        }
+        assert_eq!(polyglot_key(&game.board_state(), game.am_black()), *key);
+    }
+    let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+    assert_eq!(polyglot_key(&from_fen(fen), false), 0x22a48b5a8e47ff78);
+    let fen = "rnbqkbnr/p1pppppp/8/8/PpP4P/8/1P1PPPP1/RNBQKBNR b KQkq c3 0 3";
+    assert_eq!(polyglot_key(&from_fen(fen), true), 0x3c8123ea7b067637);
+
+    // Castling as the king taking the rook, queen promotions
+    for (fen, uci, encoded) in [
+        ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1", 0x0107),
+        ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "e8c8", 0x0f38),
+        ("8/1P5k/8/8/8/8/8/K7 w - - 0 1", "b7b8", 0x4c79),
+    ] {
+        let am_black = fen.contains(" b ");
+        let board_state = from_fen(fen);
+        let move_ = absolute_move(&parse_move(uci), am_black);
+        assert_eq!(encode_move(&board_state, am_black, &move_), encoded);
+        assert_eq!(decode_move(&board_state, am_black, encoded), Some(move_));
+    }
+    let board_state = from_fen("8/1P5k/8/8/8/8/8/K7 w - - 0 1");
+    assert_eq!(decode_move(&board_state, false, 0x1c79), None); // knight promotion
+    assert_eq!(decode_move(&board_state, false, 0x0010), None); // a1a3
+    assert!(decode_move(&board_state, false, 0x0008).is_some()); // a1a2
+
+    // Two wins with e4 and a draw with d4, the best move is e4 and both can be picked
+    let games =
+        parse_pgn("1. e4 e5 2. Nf3 Nc6 1-0\n\n1. e4 c5 2. Nf3 1-0\n\n1. d4 d5 2. c4 1/2-1/2\n")
+            .unwrap();
+    let book = Book::from_games(&games, 2, 1);
+    let start = from_fen(INITIAL_FEN);
+    let e4 = absolute_move(&parse_move("e2e4"), false);
+    let d4 = absolute_move(&parse_move("d2d4"), false);
+    assert_eq!(book.moves(&start, false), vec![(e4, 4), (d4, 1)]);
+    assert_eq!(book.pick_move(&start, false, true), Some(e4));
+    let picked: Vec<_> = (0..100)
+        .map(|_| book.pick_move(&start, false, false).unwrap())
+        .collect();
+    assert!(picked.contains(&e4) && picked.contains(&d4));
+    let after_e4 = after_move(&start, &e4);
+    assert_eq!(book.moves(&after_e4, true).len(), 2);
+    // Played once each, and beyond max_ply
+    assert!(Book::from_games(&games, 2, 2)
+        .moves(&after_e4, true)
+        .is_empty());
+    assert!(book
+        .moves(
+            &after_move(&after_e4, &book.moves(&after_e4, true)[0].0),
+            false
+        )
+        .is_empty());
+
+    let bytes = book.to_bytes();
+    assert_eq!(bytes.len(), 16 * book.entries.len());
+    assert_eq!(Book::from_bytes(&bytes).unwrap().entries, book.entries);
+    assert!(Book::from_bytes(&bytes[1..]).is_err());
 }
@@ -0,0 +1,110 @@
+use std::fmt;
+
+use crate::board::{nullmove, BoardState};
+use crate::evaluation::{tapered_score, ClassicalEvaluator, BOARD_TERMS, CLASSICAL_TERMS};
+use crate::parameters::{EvalParameters, DEFAULT_PARAMETERS};
+use crate::pieces::MAX_PHASE;
+
+// Name, (midgame, endgame) score of my pieces and (midgame, endgame) score of the opponent's
+pub type EvalTerm = (&'static str, (i32, i32), (i32, i32));
+
+fn terms_total(terms: &[EvalTerm]) -> (i32, i32) {
+    terms
+        .iter()
+        .fold((0, 0), |(midgame, endgame), (_, mine, opponent)| {
+            (midgame + mine.0 - opponent.0, endgame + mine.1 - opponent.1)
+        })
+}
+
+// Breakdown of the classical evaluation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvalTrace {
+    pub terms: Vec<EvalTerm>,
+    pub phase: i32,
+}
+
+impl EvalTrace {
+    // Midgame and endgame score from the point of view of the side to move
+    pub fn total(&self) -> (i32, i32) {
+        terms_total(&self.terms)
+    }
+
+    // Tapered like ClassicalEvaluator::evaluate, the board terms and the leaf terms separately
+    pub fn score(&self) -> i32 {
+        let tapered_total = |terms: &[EvalTerm]| {
+            let (midgame_score, endgame_score) = terms_total(terms);
+            tapered_score(midgame_score, endgame_score, self.phase)
+        };
+        let (board_terms, leaf_terms) = self.terms.split_at(BOARD_TERMS);
+        tapered_total(board_terms) + tapered_total(leaf_terms)
+    }
+
+    // Table with a row for each term, side_names are the names of the side to move and the
+    // opponent, e.g. ("White", "Black")
+    pub fn render_table(&self, side_names: (&str, &str)) -> String {
+        let cell = |(midgame, endgame): (i32, i32)| format!("{:>7} {:>6} ", midgame, endgame);
+        let separator = format!("{:-<15}+{:-<15}+{:-<15}+{:-<15}\n", "", "", "", "");
+
+        let mut table = format!(
+            "{:<15}|{:^15}|{:^15}|{:^15}\n",
+            "Term", side_names.0, side_names.1, "Total"
+        );
+        table.push_str(&format!(
+            "{:<15}|{:>7} {:>6} |{:>7} {:>6} |{:>7} {:>6} \n",
+            "", "MG", "EG", "MG", "EG", "MG", "EG"
+        ));
+        table.push_str(&separator);
+        for (name, mine, opponent) in &self.terms {
+            let total = (mine.0 - opponent.0, mine.1 - opponent.1);
+            table.push_str(&format!(
+                "{:<15}|{}|{}|{}\n",
+                name,
+                cell(*mine),
+                cell(*opponent),
+                cell(total)
+            ));
+        }
+        table.push_str(&separator);
+        table.push_str(&format!(
+            "{:<15}|{:15}|{:15}|{}\n",
+            "Total",
+            "",
+            "",
+            cell(self.total())
+        ));
+        table.push_str(&format!(
+            "Phase {} of {}, score {} for {}\n",
+            self.phase,
+            MAX_PHASE,
+            self.score(),
+            side_names.0
+        ));
+        table
+    }
+}
+
+impl fmt::Display for EvalTrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.render_table(("Side to move", "Opponent")))
+    }
+}
+
+// Per term and per side contributions to the evaluation of ClassicalEvaluator
+pub fn eval_trace(board_state: &BoardState) -> EvalTrace {
+    eval_trace_with(&DEFAULT_PARAMETERS, board_state)
+}
+
+// Like eval_trace, for an evaluator with the given parameters
+pub fn eval_trace_with(parameters: &EvalParameters, board_state: &BoardState) -> EvalTrace {
+    let evaluator = ClassicalEvaluator::without_pawn_hash_table(parameters);
+    let my_terms = evaluator.side_terms(board_state);
+    let opponent_terms = evaluator.side_terms(&nullmove(board_state));
+    EvalTrace {
+        terms: CLASSICAL_TERMS
+            .iter()
+            .zip(my_terms.iter().zip(opponent_terms.iter()))
+            .map(|(&name, (&mine, &opponent))| (name, mine, opponent))
+            .collect(),
+        phase: board_state.phase,
+    }
+}
@@ -0,0 +1,222 @@
+use log::info;
+
+use crate::board::BoardState;
+use crate::evaluation::{ClassicalEvaluator, Evaluator, PieceSquareEvaluator};
+use crate::parameters::EvalParameters;
+use crate::search::Searcher;
+use crate::ui::from_fen;
+
+// Texel tuning: find the evaluation parameters that best predict the results of the games the
+// positions come from, see https://www.chessprogramming.org/Texel%27s_Tuning_Method
+
+// Position and result from the point of view of the side to move: 1 win, 0.5 draw, 0 loss
+pub type LabelledPosition = (BoardState, f64);
+
+// Piece values, skipping kings, then all the piece square tables
+const TUNABLE_PIECE_VALUES: usize = 5;
+pub const TUNABLE_PARAMETERS: usize = 2 * TUNABLE_PIECE_VALUES + 2 * 6 * 64;
+
+// A FEN followed by the result of the game, either as 1-0, 0-1, 1/2-1/2 or as a number
+// from the point of view of white, optionally between brackets or quotes, like in
+// "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3 [0.5]" or
+// "8/8/4k3/8/8/4K3/4P3/8 w - - c9 \"1-0\";"
+pub fn parse_labelled_position(line: &str) -> Result<LabelledPosition, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 5 {
+        return Err(format!("expected a FEN and a result, found {}", line));
+    }
+    let result =
+        fields[fields.len() - 1].trim_matches(|c| c == '[' || c == ']' || c == '"' || c == ';');
+    let white_result = match result {
+        "1-0" => 1.0,
+        "0-1" => 0.0,
+        "1/2-1/2" => 0.5,
+        _ => match result.parse::<f64>() {
+            Ok(value) if (0.0..=1.0).contains(&value) => value,
+            _ => return Err(format!("invalid result {}", result)),
+        },
+    };
+    if fields[1] != "w" && fields[1] != "b" {
+        return Err(format!("invalid side to move {}", fields[1]));
+    }
+    // Move counters are not needed, and EPD files don't have them
+    let fen = format!(
+        "{} {} {} {} 0 1",
+        fields[0], fields[1], fields[2], fields[3]
+    );
+    let board_state = from_fen(&fen);
+    if fields[1] == "w" {
+        Ok((board_state, white_result))
+    } else {
+        Ok((board_state, 1.0 - white_result))
+    }
+}
+
+// One labelled position per line, empty lines and lines starting with # are skipped
+pub fn load_dataset(path: &str) -> Result<Vec<LabelledPosition>, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path, error))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            parse_labelled_position(line)
+                .map_err(|error| format!("{}:{}: {}", path, line_number + 1, error))
+        })
+        .collect()
+}
+
+// Quiescence search scores of the positions, evaluated with the given parameters
+pub fn quiescence_scores(parameters: &EvalParameters, positions: &[LabelledPosition]) -> Vec<i32> {
+    let mut searcher = Searcher::with_evaluator(ClassicalEvaluator::with_parameters(parameters));
+    quiescence_scores_with(&mut searcher, positions)
+}
+
+// Like quiescence_scores, with the evaluator of the searcher
+pub fn quiescence_scores_with<E: Evaluator>(
+    searcher: &mut Searcher<E>,
+    positions: &[LabelledPosition],
+) -> Vec<i32> {
+    positions
+        .iter()
+        .map(|(board_state, _)| searcher.quiescence_score(board_state))
+        .collect()
+}
+
+// The tables of the searcher with other parameters. The pawn hash table is kept too, the pawn
+// structure terms aren't tuned
+fn with_parameters<'a>(
+    mut searcher: Searcher<ClassicalEvaluator>,
+    parameters: &'a EvalParameters,
+) -> Searcher<ClassicalEvaluator<'a>> {
+    let pawn_hash_table = std::mem::take(&mut searcher.evaluator.pawn_hash_table);
+    searcher.with_new_evaluator(ClassicalEvaluator {
+        piece_squares: PieceSquareEvaluator::with_parameters(parameters),
+        pawn_hash_table,
+    })
+}
+
+// Expected result for a score, scaling_constant adapts the score to the usual Elo scale
+pub fn sigmoid(score: i32, scaling_constant: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-scaling_constant * f64::from(score) / 400.0))
+}
+
+pub fn mean_squared_error(
+    scores: &[i32],
+    positions: &[LabelledPosition],
+    scaling_constant: f64,
+) -> f64 {
+    let total: f64 = scores
+        .iter()
+        .zip(positions)
+        .map(|(&score, (_, result))| (result - sigmoid(score, scaling_constant)).powi(2))
+        .sum();
+    total / positions.len() as f64
+}
+
+// Scaling constant that minimises the error of the scores, searched with smaller and smaller
+// steps around the best one so far
+pub fn fit_scaling_constant(scores: &[i32], positions: &[LabelledPosition]) -> f64 {
+    let mut best = 1.0;
+    for step in [0.1, 0.01, 0.001].iter() {
+        let candidates = (-10..=10).map(|k| best + f64::from(k) * step);
+        best = candidates
+            .filter(|&scaling_constant| scaling_constant > 0.0)
+            .map(|scaling_constant| {
+                let error = mean_squared_error(scores, positions, scaling_constant);
+                (error, scaling_constant)
+            })
+            .fold((f64::INFINITY, best), |best, candidate| {
+                if candidate.0 < best.0 {
+                    candidate
+                } else {
+                    best
+                }
+            })
+            .1;
+    }
+    best
+}
+
+// Error of the parameters on the positions, with a fixed scaling constant
+pub fn parameters_error(
+    parameters: &EvalParameters,
+    positions: &[LabelledPosition],
+    scaling_constant: f64,
+) -> f64 {
+    let scores = quiescence_scores(parameters, positions);
+    mean_squared_error(&scores, positions, scaling_constant)
+}
+
+// The index-th tunable parameter, None for pawn table entries on the first and last rank
+pub fn tunable_parameter(parameters: &mut EvalParameters, index: usize) -> Option<&mut i32> {
+    if index < 2 * TUNABLE_PIECE_VALUES {
+        let piece = index % TUNABLE_PIECE_VALUES;
+        return Some(if index < TUNABLE_PIECE_VALUES {
+            &mut parameters.midgame_piece_values[piece]
+        } else {
+            &mut parameters.endgame_piece_values[piece]
+        });
+    }
+    let index = index - 2 * TUNABLE_PIECE_VALUES;
+    let (table, square) = (index / 64 % 6, index % 64);
+    if table == 0 && !(8..56).contains(&square) {
+        return None;
+    }
+    Some(if index < 6 * 64 {
+        &mut parameters.midgame_tables[table][square]
+    } else {
+        &mut parameters.endgame_tables[table][square]
+    })
+}
+
+// Local search: move each parameter by step while the error improves. Stops after the
+// given number of passes over all the parameters, or when no change helps
+pub fn tune(
+    parameters: &EvalParameters,
+    positions: &[LabelledPosition],
+    scaling_constant: f64,
+    passes: usize,
+    step: i32,
+) -> EvalParameters {
+    // A single searcher for all the candidates, allocating its tables takes longer than
+    // scoring the positions
+    let mut searcher = Searcher::with_evaluator(ClassicalEvaluator::with_parameters(parameters));
+    let mut best_parameters = parameters.clone();
+    let mut best_error = mean_squared_error(
+        &quiescence_scores_with(&mut searcher, positions),
+        positions,
+        scaling_constant,
+    );
+    info!("Starting error {:.8}", best_error);
+    for pass in 1..=passes {
+        let mut improved = false;
+        for index in 0..TUNABLE_PARAMETERS {
+            for &delta in [step, -step].iter() {
+                let mut candidate = best_parameters.clone();
+                match tunable_parameter(&mut candidate, index) {
+                    Some(value) => *value += delta,
+                    None => break,
+                }
+                if candidate.validate().is_err() {
+                    continue;
+                }
+                let mut candidate_searcher = with_parameters(searcher, &candidate);
+                let scores = quiescence_scores_with(&mut candidate_searcher, positions);
+                searcher = with_parameters(candidate_searcher, parameters);
+                let error = mean_squared_error(&scores, positions, scaling_constant);
+                if error < best_error {
+                    best_parameters = candidate;
+                    best_error = error;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        info!("Pass {} error {:.8}", pass, best_error);
+        if !improved {
+            break;
+        }
+    }
+    best_parameters
+}
@@ -1,47 +1,254 @@
 use log::{info, trace, warn};
+use std::sync::Arc;
 use std::time::Duration;
 
+use crate::bench::{bench, BENCH_DEPTH};
 use crate::board::{after_move, gen_moves, A8, BOARD_SIZE, H8, INITIAL_BOARD_STATE};
+use crate::book::Book;
+use crate::evaluation::ClassicalEvaluator;
+use crate::game::INITIAL_FEN;
+use crate::parameters::{EvalParameters, DEFAULT_PARAMETERS};
 use crate::pieces::Square;
-use crate::search::Searcher;
-use crate::ui::{parse_move, render_move};
+use crate::search::{SearchAlgorithm, SearchConfig, SearchLimits, SearchStatistics, Searcher};
+use crate::trace::eval_trace_with;
+use crate::ui::{from_fen, parse_move, render_move, render_san};
+use crate::xboard::xboard_loop_with_parameters;
 
-fn read_line() -> String {
+pub fn read_line() -> String {
     let mut line = String::new();
     std::io::stdin().read_line(&mut line).unwrap();
     line.pop();
     line
 }
 
+// Fixed limits like go depth 6 or go movetime 1000, otherwise the time is based on the clocks.
+// Limits without a valid value are ignored
+pub fn go_limits(infos: &[&str], am_black: bool) -> SearchLimits {
+    let limit = |name: &str| {
+        let index = infos.iter().position(|&info| info == name)?;
+        let value = infos
+            .get(index + 1)
+            .and_then(|value| value.parse::<u32>().ok());
+        if value.is_none() {
+            warn!("Ignoring go {} without a valid value", name);
+        }
+        value
+    };
+    let limits = SearchLimits {
+        depth: limit("depth").map(|depth| depth as i32),
+        nodes: limit("nodes"),
+        time: limit("movetime").map(|millis| Duration::from_millis(millis.into())),
+        mate: limit("mate").map(|moves| moves as i32),
+    };
+    if limits == SearchLimits::default() {
+        SearchLimits::time(time_for_move(infos, am_black))
+    } else {
+        limits
+    }
+}
+
+// TODO: refactor time management
+fn time_for_move(infos: &[&str], am_black: bool) -> Duration {
+    // Command format is going to be:
+    // go wtime 391360 btime 321390 winc 8000 binc 8000
+    // Just try to copy opponent time management
+    let time_difference: i32 = if infos.len() < 9 {
+        4_000 // If I have no information, assume I have 4 seconds, used also for first move
+    } else if am_black {
+        infos[4].parse::<i32>().expect("Failed to btime")
+            - infos[2].parse::<i32>().expect("Failed to parse wtime")
+    } else {
+        infos[2].parse::<i32>().expect("Failed to parse wtime")
+            - infos[4].parse::<i32>().expect("Failed to parse btime")
+    };
+
+    let increment: i32 = if infos.len() < 9 {
+        0 // Assume no increment
+    } else if am_black {
+        infos[8].parse::<i32>().expect("Failed to parse binc")
+    } else {
+        infos[6].parse::<i32>().expect("Failed to parse winc")
+    };
+
+    let mut nanos_for_move: i64 = i64::from(time_difference + increment - 3_000) * 1_000_000;
+
+    if nanos_for_move < (increment * 800_000).into() {
+        nanos_for_move = (increment * 800_000).into();
+    }
+
+    if nanos_for_move > 40_000_000 {
+        nanos_for_move = 40_000_000;
+    }
+
+    if nanos_for_move > 1_700_000_000 {
+        nanos_for_move -= 200_000_000 // Account for lag
+    } else {
+        nanos_for_move = 500_000_000 // Minimum reasonable move time
+    }
+
+    let time_for_move = Duration::new(
+        nanos_for_move as u64 / 1_000_000_000,
+        (nanos_for_move % 1_000_000_000) as u32,
+    );
+    info!(
+        "Computing move giving time {:?} with {}s difference and {}s increment",
+        time_for_move,
+        time_difference / 1000,
+        increment / 1000,
+    );
+    time_for_move
+}
+
+// Options changing the search, false if the name is unknown
+pub fn set_search_option(config: &mut SearchConfig, name: &str, value: &str) -> bool {
+    let enabled = value == "true";
+    match name {
+        "Algorithm" if value == "PVS" => config.algorithm = SearchAlgorithm::Pvs,
+        "Algorithm" => config.algorithm = SearchAlgorithm::MtdBi,
+        "LateMoveReductions" => config.late_move_reductions = enabled,
+        "FutilityPruning" => config.futility_pruning = enabled,
+        "ReverseFutilityPruning" => config.reverse_futility_pruning = enabled,
+        _ => return false,
+    }
+    true
+}
+
+// New tables for each command
+fn new_searcher(
+    parameters: &EvalParameters,
+    config: SearchConfig,
+) -> Searcher<ClassicalEvaluator<'_>> {
+    let mut searcher = Searcher::with_evaluator(ClassicalEvaluator::with_parameters(parameters));
+    searcher.config = config;
+    searcher
+}
+
 pub fn uci_loop() {
-    println!("Sunfish_rs");
+    uci_loop_with_parameters(Arc::new(DEFAULT_PARAMETERS.clone()))
+}
+
+// Evaluation parameters can be changed later with the EvalFile option. Interfaces starting with
+// the xboard command get the XBoard protocol instead
+pub fn uci_loop_with_parameters(mut parameters: Arc<EvalParameters>) {
     let mut board_state = INITIAL_BOARD_STATE;
     let mut am_black = false;
+    let mut config = SearchConfig::default();
+    let mut book = None;
+    let mut best_book_move = false;
+    let mut first_command = Some(read_line());
+    if first_command.as_deref() == Some("xboard") {
+        info!("Switching to the XBoard protocol");
+        return xboard_loop_with_parameters(
+            &parameters,
+            config,
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout(),
+        )
+        .unwrap();
+    }
+    println!("Sunfish_rs");
     loop {
-        let mut searcher = Searcher::default();
-        let next_command = read_line();
+        let next_command = first_command.take().unwrap_or_else(read_line);
         trace!("Received command {}", next_command);
         match next_command.split(' ').next().unwrap() {
             "quit" => return,
-            "uci" => println!("uciok"),
+            "uci" => {
+                println!("option name EvalFile type string default <empty>");
+                println!("option name Book type string default <empty>");
+                println!("option name BestBookMove type check default false");
+                println!("option name Algorithm type combo default MTD-bi var MTD-bi var PVS");
+                println!(
+                    "option name LateMoveReductions type check default {}",
+                    config.late_move_reductions
+                );
+                println!(
+                    "option name FutilityPruning type check default {}",
+                    config.futility_pruning
+                );
+                println!(
+                    "option name ReverseFutilityPruning type check default {}",
+                    config.reverse_futility_pruning
+                );
+                println!("uciok");
+            }
+            "setoption" => {
+                // setoption name EvalFile value path/to/parameters.toml
+                match next_command.split_once(" value ") {
+                    Some((option, path)) if option.trim_end().ends_with("name EvalFile") => {
+                        let path = path.trim();
+                        if path.is_empty() || path == "<empty>" {
+                            parameters = Arc::new(DEFAULT_PARAMETERS.clone());
+                        } else {
+                            match EvalParameters::load(path) {
+                                Ok(loaded) => {
+                                    info!("Loaded evaluation parameters from {}", path);
+                                    parameters = Arc::new(loaded);
+                                }
+                                Err(error) => {
+                                    warn!("Keeping the previous evaluation parameters, {}", error);
+                                    println!("info string {}", error);
+                                }
+                            }
+                        }
+                    }
+                    // setoption name Book value path/to/book.bin
+                    Some((option, path)) if option.trim_end().ends_with("name Book") => {
+                        let path = path.trim();
+                        if path.is_empty() || path == "<empty>" {
+                            book = None;
+                        } else {
+                            match Book::load(path) {
+                                Ok(loaded) => {
+                                    info!(
+                                        "Loaded {} book entries from {}",
+                                        loaded.entries.len(),
+                                        path
+                                    );
+                                    book = Some(loaded);
+                                }
+                                Err(error) => {
+                                    warn!("Keeping the previous book, {}", error);
+                                    println!("info string {}", error);
+                                }
+                            }
+                        }
+                    }
+                    Some((option, value)) if option.trim_end().ends_with("name BestBookMove") => {
+                        best_book_move = value.trim() == "true";
+                    }
+                    // setoption name LateMoveReductions value false
+                    Some((option, value)) => {
+                        let name = option.trim_end().rsplit(' ').next().unwrap_or_default();
+                        if !set_search_option(&mut config, name, value.trim()) {
+                            warn!("UNKNOWN OPTION {}", next_command);
+                        }
+                    }
+                    _ => warn!("UNKNOWN OPTION {}", next_command),
+                }
+            }
             "isready" => println!("readyok"),
             "ucinewgame" => board_state = INITIAL_BOARD_STATE,
             "position" => {
                 //position startpos moves d2d4 d7d5 e2e4 d5e4
+                //position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1 moves e1g1
                 info!("loading moves");
-                let moves: Vec<&str> = next_command.split(' ').collect();
-                if moves.len() == 2 && moves[1] != "startpos" {
-                    warn!("UNKNOWN FORMAT!");
-                    panic!();
-                } else if moves.len() > 2
-                    && (moves[0] != "position" || moves[1] != "startpos" || moves[2] != "moves")
-                {
+                let words: Vec<&str> = next_command.split(' ').collect();
+                let (fen, moves) = match words.get(1) {
+                    Some(&"startpos") => (INITIAL_FEN.to_string(), &words[2..]),
+                    Some(&"fen") if words.len() >= 8 => (words[2..8].join(" "), &words[8..]),
+                    _ => {
+                        warn!("UNKNOWN FORMAT!");
+                        panic!();
+                    }
+                };
+                if !moves.is_empty() && moves[0] != "moves" {
                     warn!("UNKNOWN FORMAT!");
                     panic!();
                 }
-                board_state = INITIAL_BOARD_STATE;
-                am_black = false;
-                for move_ in moves.iter().skip(3) {
+                let mut searcher = new_searcher(&parameters, config);
+                board_state = from_fen(&fen);
+                am_black = fen.split(' ').nth(1) == Some("b");
+                for move_ in moves.iter().skip(1) {
                     let mut parsed_move = parse_move(move_);
                     if am_black {
                         parsed_move.0 = BOARD_SIZE - 1 - parsed_move.0;
@@ -59,63 +266,55 @@ pub fn uci_loop() {
                 }
                 // print_board(&board_state);
             }
+            "bench" => {
+                // bench [depth], same result on any machine except for the time
+                let depth = next_command
+                    .split(' ')
+                    .nth(1)
+                    .map_or(BENCH_DEPTH, |depth| match depth.parse() {
+                        Ok(depth) if depth > 0 => depth,
+                        _ => {
+                            warn!("Ignoring bench depth {}, using {}", depth, BENCH_DEPTH);
+                            BENCH_DEPTH
+                        }
+                    });
+                println!("{}", bench(&parameters, config, depth));
+            }
+            "eval" => {
+                let side_names = if am_black {
+                    ("Black", "White")
+                } else {
+                    ("White", "Black")
+                };
+                print!(
+                    "{}",
+                    eval_trace_with(&parameters, &board_state).render_table(side_names)
+                );
+            }
             "go" => {
-                // TODO: refactor time management, should be somewhere else
-
-                // Command format is going to be:
-                // go wtime 391360 btime 321390 winc 8000 binc 8000
                 let infos: Vec<&str> = next_command.split(' ').collect();
 
-                // Just try to copy opponent time management
-                let time_difference: i32 = if infos.len() < 9 {
-                    4_000 // If I have no information, assume I have 4 seconds, used also for first move
-                } else if am_black {
-                    infos[4].parse::<i32>().expect("Failed to btime")
-                        - infos[2].parse::<i32>().expect("Failed to parse wtime")
-                } else {
-                    infos[2].parse::<i32>().expect("Failed to parse wtime")
-                        - infos[4].parse::<i32>().expect("Failed to parse btime")
+                let limits = go_limits(&infos, am_black);
+                let mut searcher = new_searcher(&parameters, config);
+                let book_move = book
+                    .as_ref()
+                    .and_then(|book: &Book| book.pick_move(&board_state, am_black, best_book_move));
+                let (top_move, _score, _depth, stats) = match book_move {
+                    Some(book_move) => {
+                        info!("Playing a book move");
+                        (Some(book_move), 0, 0, SearchStatistics::default())
+                    }
+                    None => searcher.search(board_state, limits),
                 };
-
-                let increment: i32 = if infos.len() < 9 {
-                    0 // Assume no increment
-                } else if am_black {
-                    infos[8].parse::<i32>().expect("Failed to parse binc")
-                } else {
-                    infos[6].parse::<i32>().expect("Failed to parse winc")
+                // The null move, when the game is over
+                let Some(mut top_move) = top_move else {
+                    warn!("No legal move to play");
+                    println!("bestmove 0000");
+                    continue;
                 };
-
-                let mut nanos_for_move: i64 =
-                    i64::from(time_difference + increment - 3_000) * 1_000_000;
-
-                if nanos_for_move < (increment * 800_000).into() {
-                    nanos_for_move = (increment * 800_000).into();
-                }
-
-                if nanos_for_move > 40_000_000 {
-                    nanos_for_move = 40_000_000;
-                }
-
-                if nanos_for_move > 1_700_000_000 {
-                    nanos_for_move -= 200_000_000 // Account for lag
-                } else {
-                    nanos_for_move = 500_000_000 // Minimum reasonable move time
-                }
-
-                let time_for_move = Duration::new(
-                    nanos_for_move as u64 / 1_000_000_000,
-                    (nanos_for_move % 1_000_000_000) as u32,
-                );
-                info!(
-                    "Computing move giving time {:?} with {}s difference and {}s increment",
-                    time_for_move,
-                    time_difference / 1000,
-                    increment / 1000,
-                );
-                // TODO parse_movetime
-                let (mut top_move, _score, _depth) = searcher.search(board_state, time_for_move);
                 let is_promotion = (A8 <= top_move.1 && top_move.1 <= H8)
                     && board_state.board[top_move.0] == Square::MyPawn;
+                let san = render_san(&board_state, am_black, &top_move);
                 if am_black {
                     top_move.0 = BOARD_SIZE - 1 - top_move.0;
                     top_move.1 = BOARD_SIZE - 1 - top_move.1;
@@ -125,15 +324,16 @@ pub fn uci_loop() {
                 } else {
                     println!("bestmove {} ponder e7e5", render_move(&top_move));
                 }
-                info!("Sending bestmove {}", render_move(&top_move));
+                info!("Sending bestmove {} ({})", render_move(&top_move), san);
                 info!(
                     "Searched {} nodes, reached depth {}, estimate score {}, tables at {} and {}",
-                    searcher.nodes,
+                    stats.nodes,
                     _depth,
                     _score,
                     searcher.move_transposition_table.len(),
                     searcher.score_transposition_table.len()
                 );
+                info!("Search statistics: {}", stats);
             }
             _ => {
                 warn!("UNKNOWN COMMAND {}", next_command);
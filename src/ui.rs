@@ -1,4 +1,8 @@
-use crate::board::{rotate, static_score, BoardState, A1, A8, BOARD_SIDE, BOARD_SIZE, PADDING};
+use crate::board::{
+    after_move, board_evaluator, can_capture_king, is_in_check, legal_moves, rotate, BoardState,
+    A1, A8, BOARD_SIDE, BOARD_SIZE, H8, PADDING,
+};
+use crate::evaluation::Evaluator;
 use crate::pieces::Square;
 
 pub fn parse_move(move_: &str) -> (usize, usize) {
@@ -11,8 +15,7 @@ pub fn parse_coordinates(coordinates: &str) -> usize {
     let mut chars = coordinates.chars();
     let file = chars.next().expect("Failed to parse coordinates");
     let rank = chars.next().expect("Failed to parse coordinates");
-    A1 + (file as i32 - 'a' as i32) as usize
-        - BOARD_SIDE as usize * ((rank as i32 - '1' as i32) as usize)
+    A1 + (file as i32 - 'a' as i32) as usize - BOARD_SIDE * ((rank as i32 - '1' as i32) as usize)
 }
 
 pub fn render_move(move_: &(usize, usize)) -> String {
@@ -25,6 +28,188 @@ fn render_coordinates(position: usize) -> String {
     [file as char, rank as char].iter().collect()
 }
 
+// Black's board is rotated, moves are rendered and parsed from white's point of view
+pub fn absolute_move(move_: &(usize, usize), am_black: bool) -> (usize, usize) {
+    if am_black {
+        (BOARD_SIZE - 1 - move_.0, BOARD_SIZE - 1 - move_.1)
+    } else {
+        *move_
+    }
+}
+
+fn san_piece(c: char) -> Option<Square> {
+    match c.to_ascii_uppercase() {
+        'P' => Some(Square::MyPawn),
+        'N' => Some(Square::MyKnight),
+        'B' => Some(Square::MyBishop),
+        'R' => Some(Square::MyRook),
+        'Q' => Some(Square::MyQueen),
+        'K' => Some(Square::MyKing),
+        _ => None,
+    }
+}
+
+// Standard algebraic notation like Nbd7, exd6, e8=Q+ or O-O, None if it doesn't match exactly
+// one legal move. Also accepts what people and other programs write instead: long algebraic
+// like Ng1-f3 or e2e4, lowercase pieces, figurines, 0-0, e8Q, e8(Q), ed5, exd6 e.p. and so on.
+// Pawns always promote to queens
+pub fn parse_san(board_state: &BoardState, am_black: bool, san: &str) -> Option<(usize, usize)> {
+    let san = san.trim().trim_end_matches(['+', '#', '!', '?']);
+    let san = san
+        .strip_suffix("e.p.")
+        .or_else(|| san.strip_suffix("ep"))
+        .unwrap_or(san);
+    let mut chars: Vec<char> = san
+        .chars()
+        .filter_map(|c| match c {
+            '♔' | '♚' => Some('K'),
+            '♕' | '♛' => Some('Q'),
+            '♖' | '♜' => Some('R'),
+            '♗' | '♝' => Some('B'),
+            '♘' | '♞' => Some('N'),
+            '♙' | '♟' | '-' | ':' | 'x' | '=' | '(' | ')' | '/' | ' ' | '.' => None,
+            c => Some(c),
+        })
+        .collect();
+    let castling = match chars.iter().collect::<String>().to_lowercase().as_str() {
+        "oo" | "00" => Some(2),
+        "ooo" | "000" => Some(-2),
+        _ => None,
+    };
+    // Promotion piece after the rank
+    let is_promotion = chars.len() >= 3
+        && chars[chars.len() - 2].is_ascii_digit()
+        && chars[chars.len() - 1].is_ascii_alphabetic();
+    if is_promotion && chars.pop().map(|c| c.to_ascii_uppercase()) != Some('Q') {
+        return None;
+    }
+
+    let legal_moves = legal_moves(board_state);
+    let distance = |move_: &(usize, usize)| {
+        let (start, end) = absolute_move(move_, am_black);
+        end as i32 - start as i32
+    };
+    let is_castling = |move_: &(usize, usize)| {
+        board_state.board[move_.0] == Square::MyKing && distance(move_).abs() == 2
+    };
+    if let Some(direction) = castling {
+        return legal_moves
+            .into_iter()
+            .find(|move_| is_castling(move_) && distance(move_) == direction);
+    }
+
+    // A lowercase b is a pawn of the b file, or a bishop if no pawn move matches. Without a piece
+    // letter it's a pawn move or long algebraic
+    let first = *chars.first()?;
+    let interpretations = match san_piece(first) {
+        Some(piece) if first.is_ascii_uppercase() || first != 'b' => {
+            vec![(Some(piece), &chars[1..])]
+        }
+        Some(piece) => vec![(None, &chars[..]), (Some(piece), &chars[1..])],
+        None => vec![(None, &chars[..])],
+    };
+    for (piece, rest) in interpretations {
+        if rest.len() < 2 || rest.len() > 4 {
+            continue;
+        }
+        let (disambiguation, destination) = rest.split_at(rest.len() - 2);
+        let is_long = disambiguation.len() == 2;
+        let matching: Vec<&(usize, usize)> = legal_moves
+            .iter()
+            .filter(|move_| {
+                let (start, end) = absolute_move(move_, am_black);
+                let start: Vec<char> = render_coordinates(start).chars().collect();
+                let end: Vec<char> = render_coordinates(end).chars().collect();
+                let moving = board_state.board[move_.0];
+                let piece_matches = match piece {
+                    Some(piece) => moving == piece,
+                    None => is_long || moving == Square::MyPawn,
+                };
+                piece_matches
+                    && (is_long || !is_castling(move_))
+                    && end == destination
+                    && disambiguation.iter().all(|c| {
+                        if c.is_ascii_digit() {
+                            *c == start[1]
+                        } else {
+                            *c == start[0]
+                        }
+                    })
+            })
+            .collect();
+        match matching[..] {
+            [] => continue,
+            [move_] => return Some(*move_),
+            _ => return None,
+        }
+    }
+    None
+}
+
+// Standard algebraic notation of a legal move, disambiguated by file, rank or both if needed
+pub fn render_san(board_state: &BoardState, am_black: bool, move_: &(usize, usize)) -> String {
+    let (start, end) = absolute_move(move_, am_black);
+    let moving = board_state.board[move_.0];
+    let mut san = if moving == Square::MyKing && (end as i32 - start as i32).abs() == 2 {
+        if end > start { "O-O" } else { "O-O-O" }.to_string()
+    } else {
+        let is_capture = board_state.board[move_.1].is_opponent_piece()
+            || (moving == Square::MyPawn && board_state.en_passant_position == Some(move_.1));
+        let start_coordinates = render_coordinates(start);
+        let mut san = String::new();
+        if moving == Square::MyPawn {
+            if is_capture {
+                san.push_str(&start_coordinates[..1]);
+            }
+        } else {
+            san.push(match moving {
+                Square::MyKnight => 'N',
+                Square::MyBishop => 'B',
+                Square::MyRook => 'R',
+                Square::MyQueen => 'Q',
+                _ => 'K',
+            });
+            let others: Vec<String> = legal_moves(board_state)
+                .iter()
+                .filter(|other| {
+                    other.1 == move_.1 && other.0 != move_.0 && board_state.board[other.0] == moving
+                })
+                .map(|other| render_coordinates(absolute_move(other, am_black).0))
+                .collect();
+            let same_file = others
+                .iter()
+                .any(|other| other[..1] == start_coordinates[..1]);
+            let same_rank = others
+                .iter()
+                .any(|other| other[1..] == start_coordinates[1..]);
+            if !others.is_empty() && !same_file {
+                san.push_str(&start_coordinates[..1]);
+            } else if !others.is_empty() && !same_rank {
+                san.push_str(&start_coordinates[1..]);
+            } else if !others.is_empty() {
+                san.push_str(&start_coordinates);
+            }
+        }
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&render_coordinates(end));
+        if moving == Square::MyPawn && (A8..=H8).contains(&move_.1) {
+            san.push_str("=Q");
+        }
+        san
+    };
+    let new_board_state = after_move(board_state, move_);
+    if is_in_check(&new_board_state) {
+        san.push(if legal_moves(&new_board_state).is_empty() {
+            '#'
+        } else {
+            '+'
+        });
+    }
+    san
+}
+
 impl Square {
     pub fn to_unicode(self) -> char {
         match self {
@@ -60,14 +245,18 @@ pub fn render_board(board_state: &BoardState) -> String {
         for p in row.iter().skip(PADDING).take(8) {
             rendered_board.push_str(&format!(" {}", p.to_unicode()));
         }
-        rendered_board.push_str("\n");
+        rendered_board.push('\n');
     }
     rendered_board.push_str("    a b c d e f g h \n\n");
-    rendered_board.push_str(&format!("Static score: {}\n", board_state.score));
-    if static_score(board_state.board) != board_state.score {
+    rendered_board.push_str(&format!(
+        "Static score: {} (midgame {}, endgame {}, phase {})\n",
+        board_state.score, board_state.midgame_score, board_state.endgame_score, board_state.phase
+    ));
+    let static_score = board_evaluator().static_score(&board_state.board);
+    if static_score != board_state.score {
         rendered_board.push_str(&format!(
             "STATIC SCORE ERROR, SHOULD BE: {}\n",
-            static_score(board_state.board),
+            static_score,
         ));
     }
     if board_state.en_passant_position.is_some() {
@@ -83,6 +272,80 @@ pub fn render_board(board_state: &BoardState) -> String {
     rendered_board
 }
 
+// Just the board with the real colours of the pieces, seen from the side to move
+pub fn render_position(board_state: &BoardState, am_black: bool) -> String {
+    let mut rendered = String::new();
+    for rank in 0..8 {
+        rendered.push_str(&format!(" {} ", if am_black { rank + 1 } else { 8 - rank }));
+        for file in 0..8 {
+            let square = board_state.board[A8 + rank * BOARD_SIDE + file];
+            let square = if am_black {
+                square
+            } else {
+                square.swap_color()
+            };
+            rendered.push_str(&format!(" {}", square.to_unicode()));
+        }
+        rendered.push('\n');
+    }
+    rendered.push_str(if am_black {
+        "    h g f e d c b a\n"
+    } else {
+        "    a b c d e f g h\n"
+    });
+    rendered
+}
+
+// Like from_fen, but positions that can't be parsed or played from are an error instead of a
+// panic or a broken board
+pub fn try_from_fen(fen: &str) -> Result<BoardState, String> {
+    let fields: Vec<&str> = fen.split(' ').collect();
+    if fields.len() != 6 {
+        return Err(format!("expected 6 fields in FEN {}", fen));
+    }
+    let rank_length = |rank: &str| {
+        rank.chars()
+            .map(|c| match c {
+                '1'..='8' => c.to_digit(10).map(|empty| empty as usize),
+                'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k' => Some(1),
+                _ => None,
+            })
+            .sum::<Option<usize>>()
+    };
+    let ranks: Vec<&str> = fields[0].split('/').collect();
+    if ranks.len() != 8 || ranks.iter().any(|&rank| rank_length(rank) != Some(8)) {
+        return Err(format!("invalid piece placement {}", fields[0]));
+    }
+    if fields[0].matches('K').count() != 1 || fields[0].matches('k').count() != 1 {
+        return Err(format!("expected one king for each side in {}", fields[0]));
+    }
+    if fields[1] != "w" && fields[1] != "b" {
+        return Err(format!("invalid side to move {}", fields[1]));
+    }
+    if fields[2] != "-" && !fields[2].chars().all(|c| "KQkq".contains(c)) {
+        return Err(format!("invalid castling rights {}", fields[2]));
+    }
+    let en_passant_rank = if fields[1] == "w" { '6' } else { '3' };
+    let is_en_passant_square = |square: &str| {
+        let mut chars = square.chars();
+        matches!(
+            (chars.next(), chars.next(), chars.next()),
+            (Some('a'..='h'), Some(rank), None) if rank == en_passant_rank
+        )
+    };
+    if fields[3] != "-" && !is_en_passant_square(fields[3]) {
+        return Err(format!("invalid en passant square {}", fields[3]));
+    }
+    if fields[4].parse::<u32>().is_err() || fields[5].parse::<u32>().is_err() {
+        return Err(format!("invalid move counters {} {}", fields[4], fields[5]));
+    }
+    let board_state = from_fen(fen);
+    if can_capture_king(&board_state) {
+        return Err("the side not to move is in check".to_string());
+    }
+    Ok(board_state)
+}
+
 // https://en.wikipedia.org/wiki/Forsyth%E2%80%93Edwards_Notation#Definition
 pub fn from_fen(fen: &str) -> BoardState {
     let mut new_board = [Square::Empty; BOARD_SIZE];
@@ -140,13 +403,18 @@ pub fn from_fen(fen: &str) -> BoardState {
 
     let mut boardstate = BoardState {
         board: new_board,
-        score: static_score(new_board),
+        score: 0,
+        midgame_score: 0,
+        endgame_score: 0,
+        phase: 0,
         my_castling_rights,
         opponent_castling_rights,
         en_passant_position,
         king_passant_position: None, // is not useful for legal board states
     };
 
+    board_evaluator().rescore(&mut boardstate);
+
     if turn == "b" {
         rotate(&mut boardstate);
     }
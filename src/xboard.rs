@@ -0,0 +1,229 @@
+use log::{info, trace, warn};
+use std::io::{BufRead, Write};
+use std::time::{Duration, Instant};
+
+use crate::evaluation::ClassicalEvaluator;
+use crate::game::Game;
+use crate::parameters::{EvalParameters, DEFAULT_PARAMETERS};
+use crate::search::{SearchConfig, SearchLimits, Searcher};
+use crate::ui::{absolute_move, parse_san, render_move, render_san};
+
+// Time kept for the lag between the engine and the interface
+const LAG: Duration = Duration::from_millis(50);
+const MIN_TIME_FOR_MOVE: Duration = Duration::from_millis(100);
+
+// The clock split over the moves left before the next time control, plus most of the increment
+pub fn time_for_move(clock: Duration, moves_to_go: u32, increment: Duration) -> Duration {
+    let time = clock / moves_to_go + increment * 4 / 5;
+    time.min(clock / 2)
+        .saturating_sub(LAG)
+        .max(MIN_TIME_FOR_MOVE)
+}
+
+// Seconds like 1.5, negative, NaN or infinite ones are invalid
+fn parse_seconds(seconds: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds.parse().ok()?).ok()
+}
+
+// level 40 5 0 or level 0 2:30 1.5, moves per time control, minutes and seconds of increment
+pub fn parse_level(words: &[&str]) -> Option<(u32, Duration, Duration)> {
+    let moves_per_control = words.get(1)?.parse().ok()?;
+    let (minutes, seconds) = words
+        .get(2)?
+        .split_once(':')
+        .unwrap_or((words.get(2)?, "0"));
+    let base =
+        Duration::from_secs(minutes.parse::<u64>().ok()? * 60 + seconds.parse::<u64>().ok()?);
+    let increment = parse_seconds(words.get(3)?)?;
+    Some((moves_per_control, base, increment))
+}
+
+// Searches the current position and plays the move, like the UCI loop with new tables each time
+fn engine_move(
+    game: &mut Game,
+    parameters: &EvalParameters,
+    config: SearchConfig,
+    limits: SearchLimits,
+    post: bool,
+    output: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut searcher = Searcher::with_evaluator(ClassicalEvaluator::with_parameters(parameters));
+    searcher.config = config;
+    for board_state in game.positions().skip(1) {
+        searcher.set_eval_to_zero(board_state);
+    }
+    let start = Instant::now();
+    let (move_, score, depth, stats) = searcher.search(game.board_state(), limits);
+    let Some(move_) = move_ else {
+        warn!("No legal move to play");
+        return Ok(());
+    };
+    let san = render_san(&game.board_state(), game.am_black(), &move_);
+    if post {
+        // Depth, score in centipawns, time in centiseconds, nodes and principal variation
+        writeln!(
+            output,
+            "{} {} {} {} {}",
+            depth,
+            score,
+            start.elapsed().as_millis() / 10,
+            stats.nodes,
+            san
+        )?;
+    }
+    let is_promotion = san.contains('=');
+    let rendered = render_move(&absolute_move(&move_, game.am_black()));
+    let rendered = if is_promotion {
+        rendered + "q"
+    } else {
+        rendered
+    };
+    game.play(&move_).expect("legal searcher move");
+    writeln!(output, "move {}", rendered)?;
+    info!(
+        "Sending move {} ({}), searched {} nodes",
+        rendered, san, stats.nodes
+    );
+    if let Some((result, reason)) = game.result() {
+        writeln!(output, "{} {{{}}}", result, reason)?;
+    }
+    Ok(())
+}
+
+// Chess Engine Communication Protocol, the one of XBoard and WinBoard, see
+// https://www.gnu.org/software/xboard/engine-intf.html
+pub fn xboard_loop() {
+    xboard_loop_with_parameters(
+        &DEFAULT_PARAMETERS,
+        SearchConfig::default(),
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+    )
+    .unwrap()
+}
+
+// Commands are read from the input until quit or its end, the replies written to the output
+pub fn xboard_loop_with_parameters(
+    parameters: &EvalParameters,
+    config: SearchConfig,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> std::io::Result<()> {
+    let mut game = Game::default();
+    let mut engine_is_black = true;
+    let mut force_mode = false;
+    let mut post = false;
+    let mut clock = None;
+    let mut level = None;
+    let mut seconds_per_move = None;
+    let mut max_depth = None;
+    loop {
+        let mut next_command = String::new();
+        if input.read_line(&mut next_command)? == 0 {
+            return Ok(());
+        }
+        let next_command = next_command.trim_end();
+        trace!("Received command {}", next_command);
+        let words: Vec<&str> = next_command.split_whitespace().collect();
+        let mut think = false;
+        match words.as_slice() {
+            []
+            | ["xboard"]
+            | ["accepted", ..]
+            | ["rejected", ..]
+            | ["random"]
+            | ["hard"]
+            | ["easy"]
+            | ["computer"]
+            | ["name", ..]
+            | ["rating", ..]
+            | ["otim", ..] => {}
+            ["quit"] => return Ok(()),
+            ["protover", ..] => writeln!(
+                output,
+                "feature myname=\"Sunfish_rs\" usermove=1 setboard=1 ping=1 san=0 colors=0 \
+                 sigint=0 sigterm=0 analyze=0 done=1"
+            )?,
+            ["ping", number] => writeln!(output, "pong {}", number)?,
+            ["new"] => {
+                game = Game::default();
+                engine_is_black = true;
+                force_mode = false;
+                max_depth = None;
+            }
+            ["setboard", ..] => match Game::try_from_fen(&words[1..].join(" ")) {
+                Ok(parsed) => game = parsed,
+                Err(error) => {
+                    warn!("Rejecting setboard: {}", error);
+                    writeln!(output, "tellusererror Illegal position")?;
+                }
+            },
+            ["force"] | ["result", ..] => force_mode = true,
+            ["go"] => {
+                force_mode = false;
+                engine_is_black = game.am_black();
+                think = true;
+            }
+            ["usermove", move_] => match parse_san(&game.board_state(), game.am_black(), move_) {
+                Some(parsed) => {
+                    game.play(&parsed).expect("legal parsed move");
+                    think = !force_mode && game.am_black() == engine_is_black;
+                }
+                None => writeln!(output, "Illegal move: {}", move_)?,
+            },
+            ["undo"] => {
+                game.undo();
+            }
+            ["remove"] => {
+                game.undo();
+                game.undo();
+            }
+            ["time", centiseconds] => {
+                clock = centiseconds
+                    .parse()
+                    .ok()
+                    .map(|cs: u64| Duration::from_millis(cs * 10))
+            }
+            ["level", ..] => match parse_level(&words) {
+                Some(parsed) => level = Some(parsed),
+                None => writeln!(output, "Error (invalid level): {}", next_command)?,
+            },
+            ["st", seconds] => match parse_seconds(seconds) {
+                Some(parsed) => seconds_per_move = Some(parsed),
+                None => writeln!(output, "Error (invalid st): {}", next_command)?,
+            },
+            ["sd", depth] => max_depth = depth.parse().ok(),
+            ["post"] => post = true,
+            ["nopost"] => post = false,
+            _ => {
+                warn!("UNKNOWN COMMAND {}", next_command);
+                writeln!(output, "Error (unknown command): {}", next_command)?;
+            }
+        }
+        if think && game.result().is_none() {
+            // A fixed time per move, otherwise the clock split over the moves to the time control
+            let (moves_per_control, _, increment) =
+                level.unwrap_or((0, Duration::ZERO, Duration::ZERO));
+            let moves_to_go = if moves_per_control > 0 {
+                moves_per_control - (game.moves.len() as u32 / 2) % moves_per_control
+            } else {
+                30
+            };
+            let time = seconds_per_move.or_else(|| {
+                clock
+                    .or(level.map(|(_, base, _)| base))
+                    .map(|clock| time_for_move(clock, moves_to_go, increment))
+            });
+            let limits = SearchLimits {
+                depth: max_depth,
+                time: if time.is_none() && max_depth.is_none() {
+                    Some(Duration::from_secs(4))
+                } else {
+                    time
+                },
+                ..SearchLimits::default()
+            };
+            engine_move(&mut game, parameters, config, limits, post, output)?;
+        }
+    }
+}